@@ -0,0 +1,266 @@
+use std::{fmt, str::FromStr, time::Duration};
+
+use jiff::{SignedDuration, Span, SpanRelativeTo, SpanRound, Unit};
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+
+/// The maximum age of an update before another is due.
+///
+/// Accepts friendly durations like `30m`, `4h`, `1d12h`, or `1w`, ISO 8601
+/// durations like `PT4H`, and plain integers, which are read as hours.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MaxAge(SignedDuration);
+
+impl MaxAge {
+    pub fn duration(self) -> SignedDuration {
+        self.0
+    }
+}
+
+/// Describes a duration the way a person would, to the nearest minute: e.g.
+/// "3h 12m" or "2d 4h 5m".
+pub fn friendly(duration: SignedDuration) -> String {
+    let rounding = SpanRound::new()
+        .largest(Unit::Day)
+        .smallest(Unit::Minute)
+        .days_are_24_hours();
+
+    let span = Span::try_from(duration.abs()).and_then(|span| span.round(rounding));
+    match span {
+        Ok(span) if span.is_zero() => String::from("0m"),
+        Ok(span) => format!("{span:#}"),
+        Err(_) => format!("{:#}", duration.abs()),
+    }
+}
+
+impl Default for MaxAge {
+    fn default() -> Self {
+        MaxAge(SignedDuration::from_hours(4))
+    }
+}
+
+impl FromStr for MaxAge {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+
+        // Plain integers have always meant hours, and there are shell
+        // functions out there that depend on that.
+        let duration = match s.parse::<i64>() {
+            Ok(hours) => SignedDuration::try_from_hours(hours)
+                .ok_or_else(|| format!("max age of {hours} hours is out of range"))?,
+            Err(_) => parse_span(s)?,
+        };
+
+        if duration.is_negative() || duration.is_zero() {
+            return Err(format!("max age must be greater than zero, got '{s}'"));
+        }
+
+        Ok(MaxAge(duration))
+    }
+}
+
+impl fmt::Display for MaxAge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The alternate form is jiff's "friendly" format, e.g. 1d 12h.
+        write!(f, "{:#}", self.0)
+    }
+}
+
+impl Serialize for MaxAge {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for MaxAge {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl de::Visitor<'_> for Visitor {
+            type Value = MaxAge;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a duration like \"4h\" or a number of hours")
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<MaxAge, E> {
+                v.to_string().parse().map_err(E::custom)
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<MaxAge, E> {
+                v.to_string().parse().map_err(E::custom)
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<MaxAge, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+/// How long an update may take before we give up on it.
+///
+/// Takes the same durations as [`MaxAge`], except that plain integers are
+/// read as seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timeout(SignedDuration);
+
+impl Timeout {
+    pub fn duration(self) -> Duration {
+        self.0.unsigned_abs()
+    }
+}
+
+impl FromStr for Timeout {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let duration = match s.parse::<i64>() {
+            Ok(seconds) => SignedDuration::from_secs(seconds),
+            Err(_) => parse_span(s)?,
+        };
+
+        if duration.is_negative() || duration.is_zero() {
+            return Err(format!("timeout must be greater than zero, got '{s}'"));
+        }
+
+        Ok(Timeout(duration))
+    }
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#}", self.0)
+    }
+}
+
+impl Serialize for Timeout {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Timeout {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl de::Visitor<'_> for Visitor {
+            type Value = Timeout;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a duration like \"30s\" or a number of seconds")
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Timeout, E> {
+                v.to_string().parse().map_err(E::custom)
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Timeout, E> {
+                v.to_string().parse().map_err(E::custom)
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Timeout, E> {
+                v.parse().map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+fn parse_span(s: &str) -> Result<SignedDuration, String> {
+    // Span is the more forgiving of jiff's two duration types: it understands
+    // both days and weeks. Days are taken to be 24 hours long; nobody cares
+    // about DST here.
+    let span: Span = s
+        .parse()
+        .map_err(|_| format!("invalid duration '{s}' (try something like 30m, 4h, or 1d12h)"))?;
+    span.to_duration(SpanRelativeTo::days_are_24_hours())
+        .map_err(|e| format!("invalid duration '{s}': {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn max_age(s: &str) -> Result<SignedDuration, String> {
+        s.parse::<MaxAge>().map(MaxAge::duration)
+    }
+
+    fn timeout(s: &str) -> Result<Duration, String> {
+        s.parse::<Timeout>().map(Timeout::duration)
+    }
+
+    #[test]
+    fn max_age_plain_integers_are_hours() {
+        assert_eq!(max_age("8"), Ok(SignedDuration::from_hours(8)));
+        assert_eq!(max_age(" 12 "), Ok(SignedDuration::from_hours(12)));
+    }
+
+    #[test]
+    fn max_age_friendly_durations() {
+        assert_eq!(max_age("30m"), Ok(SignedDuration::from_mins(30)));
+        assert_eq!(max_age("1d12h"), Ok(SignedDuration::from_hours(36)));
+        assert_eq!(max_age("1w"), Ok(SignedDuration::from_hours(7 * 24)));
+    }
+
+    #[test]
+    fn max_age_iso_8601() {
+        assert_eq!(max_age("PT4H"), Ok(SignedDuration::from_hours(4)));
+        assert_eq!(max_age("P1D"), Ok(SignedDuration::from_hours(24)));
+    }
+
+    #[test]
+    fn max_age_rejects_zero_and_negative() {
+        for s in ["0", "0h", "PT0S", "-1", "-4h"] {
+            let error = max_age(s).unwrap_err();
+            assert!(error.contains("greater than zero"), "{s}: {error}");
+        }
+    }
+
+    #[test]
+    fn max_age_rejects_nonsense() {
+        assert!(max_age("soon").unwrap_err().contains("invalid duration"));
+        assert!(max_age("").is_err());
+    }
+
+    #[test]
+    fn max_age_default_is_four_hours() {
+        assert_eq!(MaxAge::default().duration(), SignedDuration::from_hours(4));
+    }
+
+    #[test]
+    fn timeout_plain_integers_are_seconds() {
+        assert_eq!(timeout("90"), Ok(Duration::from_secs(90)));
+    }
+
+    #[test]
+    fn timeout_friendly_durations() {
+        assert_eq!(timeout("5m"), Ok(Duration::from_secs(300)));
+        assert_eq!(timeout("1d12h"), Ok(Duration::from_secs(36 * 3600)));
+        assert_eq!(timeout("1w"), Ok(Duration::from_secs(7 * 24 * 3600)));
+        assert_eq!(timeout("PT4H"), Ok(Duration::from_secs(4 * 3600)));
+    }
+
+    #[test]
+    fn timeout_rejects_zero_and_negative() {
+        for s in ["0", "0s", "-30", "-5m"] {
+            let error = timeout(s).unwrap_err();
+            assert!(error.contains("greater than zero"), "{s}: {error}");
+        }
+    }
+
+    #[test]
+    fn friendly_rounds_to_minutes() {
+        assert_eq!(friendly(SignedDuration::from_secs(0)), "0m");
+        assert_eq!(
+            friendly(SignedDuration::from_secs(3 * 3600 + 12 * 60 + 20)),
+            "3h 12m"
+        );
+        assert_eq!(friendly(SignedDuration::from_hours(-50)), "2d 2h");
+    }
+}
@@ -0,0 +1,37 @@
+use std::{
+    env,
+    ffi::OsString,
+    io::{self, Read},
+    process::{Child, Command, Stdio},
+};
+
+/// Starts a detached copy of ourselves to update the given repositories.
+///
+/// The child waits until its stdin is closed before touching anything, which
+/// gives us a chance to store the table (with a note saying who's working on
+/// what) without the two of us stepping on each other. Closing stdin, or just
+/// dropping the child, lets it go.
+pub fn spawn(args: Vec<OsString>) -> io::Result<Child> {
+    let mut command = Command::new(env::current_exe()?);
+    command
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
+    // Moving the child into its own process group keeps a Ctrl-C (or a
+    // closing terminal) aimed at the caller from taking it down, too.
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+
+    command.spawn()
+}
+
+/// Blocks until our parent closes stdin; see [`spawn`].
+pub fn wait_for_parent() -> io::Result<()> {
+    io::stdin().lock().read_to_end(&mut Vec::new())?;
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use std::{fmt, path::Path, time::Duration};
+
+use jiff::{SignedDuration, Timestamp};
+
+use crate::{
+    age::{self, MaxAge},
+    config::{Config, Settings},
+    error::Error,
+    key::{self, KeyMode},
+    state::State,
+    upstream::Upstream,
+};
+
+/// Asking the remote is supposed to be cheap. If it isn't, we'd rather not
+/// hold up a shell prompt finding out why.
+const REMOTE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether a repository is due for an update, and why.
+pub enum Verdict {
+    Fresh {
+        age: SignedDuration,
+        max_age: MaxAge,
+    },
+    Stale {
+        age: SignedDuration,
+        max_age: MaxAge,
+    },
+    Never,
+    Moved,
+}
+
+impl Verdict {
+    pub fn due(&self) -> bool {
+        !matches!(self, Verdict::Fresh { .. })
+    }
+}
+
+/// Works out whether a repository is due, the same way an update would, but
+/// without touching the repository or the table.
+///
+/// With `ask_remote`, a repository that isn't due by age alone is also due if
+/// its upstream has moved on since we last fetched.
+pub fn check(
+    repository: &str,
+    key_mode: KeyMode,
+    config: &Config,
+    max_age: Option<MaxAge>,
+    ask_remote: bool,
+) -> Result<Verdict, Error> {
+    let key = key::resolve(repository, key_mode)?;
+
+    // The table is only read, so a legacy key can be migrated in our copy
+    // without anybody else being any the wiser.
+    let mut table = State::new().load()?;
+    key::migrate(&mut table, repository, &key);
+
+    // Judged the same way an update would judge it.
+    let settings = Settings {
+        max_age,
+        ..Default::default()
+    }
+    .or(config.settings(repository));
+    let max_age = settings.max_age();
+
+    let Some(updated) = table.get(&key).and_then(|record| record.updated) else {
+        return Ok(Verdict::Never);
+    };
+
+    let age = Timestamp::now().duration_since(updated);
+    if age >= max_age.duration() {
+        return Ok(Verdict::Stale { age, max_age });
+    }
+
+    if ask_remote {
+        let path = Path::new(repository);
+        let upstream =
+            Upstream::resolve(path, settings.remote.as_deref(), settings.branch.as_deref())?;
+        let timeout = settings.timeout().unwrap_or(REMOTE_TIMEOUT);
+        if upstream.moved(path, true, Some(timeout))? {
+            return Ok(Verdict::Moved);
+        }
+    }
+
+    Ok(Verdict::Fresh { age, max_age })
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Verdict::Fresh { age, max_age } => {
+                let left = max_age.duration() - *age;
+                write!(
+                    f,
+                    "fresh (updated {} ago; due in {})",
+                    age::friendly(*age),
+                    age::friendly(left)
+                )
+            }
+            Verdict::Stale { age, max_age } => write!(
+                f,
+                "due (updated {} ago; max age {max_age})",
+                age::friendly(*age)
+            ),
+            Verdict::Never => f.write_str("due (never updated)"),
+            Verdict::Moved => f.write_str("due (upstream has new commits)"),
+        }
+    }
+}
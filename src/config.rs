@@ -0,0 +1,456 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use directories::{BaseDirs, ProjectDirs};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    age::{MaxAge, Timeout},
+    git,
+    hook::{Hook, Hooks},
+    strategy::Strategy,
+};
+
+/// The contents of the configuration file.
+///
+/// ```toml
+/// max_age = "8h"
+///
+/// [[repository]]
+/// path = "~/yt-dlp"
+/// max_age = "1d"
+/// remote = "upstream"
+/// branch = "master"
+/// strategy = "ff-only"
+/// verbose = true
+/// retries = 4
+/// timeout = "5m"
+/// pre_update = ["test -z \"$(pgrep yt-dlp)\""]
+/// post_update = ["make install"]
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    // Top-level settings act as defaults for every repository, whether it's
+    // listed here or named on the command line.
+    max_age: Option<MaxAge>,
+    remote: Option<String>,
+    branch: Option<String>,
+    strategy: Option<Strategy>,
+    autostash: Option<bool>,
+    allow_reset: Option<bool>,
+    verbose: Option<bool>,
+    retries: Option<u32>,
+    timeout: Option<Timeout>,
+
+    #[serde(default, rename = "repository")]
+    repositories: Vec<Repository>,
+}
+
+/// A repository listed in the configuration file.
+#[derive(Debug, Deserialize)]
+pub struct Repository {
+    path: String,
+    max_age: Option<MaxAge>,
+    remote: Option<String>,
+    branch: Option<String>,
+    strategy: Option<Strategy>,
+    autostash: Option<bool>,
+    allow_reset: Option<bool>,
+    verbose: Option<bool>,
+    retries: Option<u32>,
+    timeout: Option<Timeout>,
+
+    // Commands to run before an update (which can call it off by failing),
+    // and after an update that moved HEAD.
+    #[serde(default)]
+    pre_update: Vec<Hook>,
+    #[serde(default)]
+    post_update: Vec<Hook>,
+}
+
+/// Settings for a single repository.
+///
+/// Anything left unset falls back to the next layer down: command line, then
+/// the repository's own entry in the config file, then the file's defaults.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Settings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<MaxAge>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<Strategy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub autostash: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_reset: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verbose: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<Timeout>,
+}
+
+/// A problem found by `ensure-update config check`.
+pub enum Issue {
+    UnknownKey(String),
+    BadPath { path: String, reason: &'static str },
+}
+
+impl Config {
+    /// The default location of the configuration file, e.g.
+    /// ~/.config/ensure-update/config.toml on Linux.
+    pub fn default_path() -> Option<PathBuf> {
+        let dirs = ProjectDirs::from("", "hack-commons", "ensure-update")?;
+        Some(dirs.config_dir().join("config.toml"))
+    }
+
+    /// Loads the configuration file at `path`, or at the default location if
+    /// no path is given. A missing file at the default location is not an
+    /// error; it just means nothing has been configured.
+    pub fn load(path: Option<&Path>) -> io::Result<Config> {
+        let (config, _) = Self::load_checked(path)?;
+        Ok(config)
+    }
+
+    /// Like [`Config::load`], but also returns the keys we didn't recognize.
+    pub fn load_checked(path: Option<&Path>) -> io::Result<(Config, Vec<String>)> {
+        let (path, explicit) = match path {
+            Some(path) => (path.to_owned(), true),
+            None => match Self::default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Default::default()),
+            },
+        };
+
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !explicit => {
+                return Ok(Default::default());
+            }
+            Err(e) => {
+                return Err(io::Error::new(e.kind(), format!("{}: {e}", path.display())));
+            }
+        };
+
+        let mut unknown = Vec::new();
+        let deserializer = toml::Deserializer::parse(&text).map_err(|e| invalid(&path, e))?;
+        let mut config: Config =
+            serde_ignored::deserialize(deserializer, |key| unknown.push(key.to_string()))
+                .map_err(|e| invalid(&path, e))?;
+
+        // Relative paths are relative to the config file, not to wherever we
+        // happen to have been run from.
+        let base = path.parent().unwrap_or(Path::new("."));
+        for repository in &mut config.repositories {
+            repository.path = expand(base, &repository.path);
+        }
+
+        Ok((config, unknown))
+    }
+
+    pub fn repositories(&self) -> &[Repository] {
+        &self.repositories
+    }
+
+    /// Finds the settings for a repository, whether or not it's listed.
+    pub fn settings(&self, repository: &str) -> Settings {
+        let defaults = Settings {
+            max_age: self.max_age,
+            remote: self.remote.clone(),
+            branch: self.branch.clone(),
+            strategy: self.strategy,
+            autostash: self.autostash,
+            allow_reset: self.allow_reset,
+            verbose: self.verbose,
+            retries: self.retries,
+            timeout: self.timeout,
+        };
+
+        match self.find(repository) {
+            Some(entry) => entry.settings().or(defaults),
+            None => defaults,
+        }
+    }
+
+    /// Checks the config for unknown keys and for repositories that aren't.
+    pub fn check(&self, unknown: Vec<String>) -> Vec<Issue> {
+        let mut issues: Vec<_> = unknown.into_iter().map(Issue::UnknownKey).collect();
+
+        for repository in &self.repositories {
+            let path = Path::new(&repository.path);
+            let reason = if !path.is_dir() {
+                "not a directory"
+            } else if git::output(path, &["rev-parse", "--git-dir"]).is_err() {
+                "not a git repository"
+            } else {
+                continue;
+            };
+
+            issues.push(Issue::BadPath {
+                path: repository.path.clone(),
+                reason,
+            });
+        }
+
+        issues
+    }
+
+    /// The hooks to run around updating a repository, if it's listed.
+    pub fn hooks(&self, repository: &str) -> Hooks {
+        self.find(repository)
+            .map(|entry| Hooks {
+                pre_update: entry.pre_update.clone(),
+                post_update: entry.post_update.clone(),
+            })
+            .unwrap_or_default()
+    }
+
+    fn find(&self, repository: &str) -> Option<&Repository> {
+        // Paths are compared canonically, so "~/src/../src/tools" on the
+        // command line still finds "~/src/tools" in the config.
+        let target = fs::canonicalize(repository).ok()?;
+        self.repositories
+            .iter()
+            .find(|entry| fs::canonicalize(&entry.path).is_ok_and(|path| path == target))
+    }
+}
+
+impl Repository {
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    fn settings(&self) -> Settings {
+        Settings {
+            max_age: self.max_age,
+            remote: self.remote.clone(),
+            branch: self.branch.clone(),
+            strategy: self.strategy,
+            autostash: self.autostash,
+            allow_reset: self.allow_reset,
+            verbose: self.verbose,
+            retries: self.retries,
+            timeout: self.timeout,
+        }
+    }
+}
+
+impl Settings {
+    /// Fills in anything unset here from `fallback`.
+    pub fn or(self, fallback: Settings) -> Settings {
+        Settings {
+            max_age: self.max_age.or(fallback.max_age),
+            remote: self.remote.or(fallback.remote),
+            branch: self.branch.or(fallback.branch),
+            strategy: self.strategy.or(fallback.strategy),
+            autostash: self.autostash.or(fallback.autostash),
+            allow_reset: self.allow_reset.or(fallback.allow_reset),
+            verbose: self.verbose.or(fallback.verbose),
+            retries: self.retries.or(fallback.retries),
+            timeout: self.timeout.or(fallback.timeout),
+        }
+    }
+
+    /// The max age a repository is judged by, wherever it's judged. What it
+    /// was last updated with doesn't come into it; that's only a record.
+    pub fn max_age(&self) -> MaxAge {
+        self.max_age.unwrap_or_default()
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy.unwrap_or_default()
+    }
+
+    /// Whether uncommitted changes are stashed around the update, because
+    /// we were asked to or because the strategy does it anyway.
+    pub fn autostash(&self) -> bool {
+        self.autostash.unwrap_or_default() || self.strategy() == Strategy::RebaseAutostash
+    }
+
+    pub fn allow_reset(&self) -> bool {
+        self.allow_reset.unwrap_or_default()
+    }
+
+    pub fn verbose(&self) -> bool {
+        self.verbose.unwrap_or_default()
+    }
+
+    /// How many times to retry an update that failed for reasons that might
+    /// not last (a dropped connection, say). Defaults to two.
+    pub fn retries(&self) -> u32 {
+        self.retries.unwrap_or(2)
+    }
+
+    /// How long each git command may run before we kill it. No limit unless
+    /// one is set.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout.map(Timeout::duration)
+    }
+}
+
+fn expand(base: &Path, path: &str) -> String {
+    let home = BaseDirs::new().map(|dirs| dirs.home_dir().to_owned());
+    let expanded = match (path, home) {
+        ("~", Some(home)) => home,
+        (path, Some(home)) if path.starts_with("~/") => home.join(&path[2..]),
+        (path, _) => base.join(path),
+    };
+    expanded.to_string_lossy().into()
+}
+
+fn invalid(path: &Path, e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("{}: {e}", path.display()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+
+    /// Writes a config file into a directory of its own, alongside a
+    /// directory called "repo" for it to talk about.
+    fn write(name: &str, text: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("ensure-update-test-{}-{name}", process::id()));
+        fs::create_dir_all(dir.join("repo")).unwrap();
+        let path = dir.join("config.toml");
+        fs::write(&path, text).unwrap();
+        path
+    }
+
+    #[test]
+    fn settings_are_layered() {
+        let path = write(
+            "layered",
+            r#"
+            max_age = "8h"
+            retries = 1
+            strategy = "rebase"
+
+            [[repository]]
+            path = "repo"
+            max_age = "1d"
+            retries = 4
+            "#,
+        );
+        let config = Config::load(Some(&path)).unwrap();
+        let repo = path.with_file_name("repo");
+        let repo = repo.to_str().unwrap();
+
+        // The repository's own settings win over the file's defaults, which
+        // fill in the rest.
+        let settings = config.settings(repo);
+        assert_eq!(settings.max_age, Some("1d".parse().unwrap()));
+        assert_eq!(settings.retries(), 4);
+        assert_eq!(settings.strategy(), Strategy::Rebase);
+
+        // The command line wins over both.
+        let cli = Settings {
+            retries: Some(0),
+            strategy: Some(Strategy::FfOnly),
+            ..Default::default()
+        };
+        let settings = cli.or(config.settings(repo));
+        assert_eq!(settings.retries(), 0);
+        assert_eq!(settings.strategy(), Strategy::FfOnly);
+        assert_eq!(settings.max_age, Some("1d".parse().unwrap()));
+
+        // Anything unlisted just gets the defaults.
+        let settings = config.settings(path.parent().unwrap().to_str().unwrap());
+        assert_eq!(settings.max_age, Some("8h".parse().unwrap()));
+        assert_eq!(settings.retries(), 1);
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn unset_settings_have_defaults() {
+        let settings = Settings::default();
+        assert_eq!(settings.max_age(), MaxAge::default());
+        assert_eq!(settings.strategy(), Strategy::Pull);
+        assert_eq!(settings.retries(), 2);
+        assert_eq!(settings.timeout(), None);
+        assert!(!settings.autostash());
+        assert!(!settings.allow_reset());
+    }
+
+    #[test]
+    fn paths_are_relative_to_the_config_file() {
+        let base = Path::new("/etc/ensure-update");
+        assert_eq!(expand(base, "tools"), "/etc/ensure-update/tools");
+        assert_eq!(expand(base, "/src/tools"), "/src/tools");
+
+        let home = BaseDirs::new().unwrap().home_dir().to_owned();
+        assert_eq!(expand(base, "~"), home.to_string_lossy());
+        assert_eq!(
+            expand(base, "~/src/tools"),
+            home.join("src/tools").to_string_lossy()
+        );
+        // Only a tilde on its own means home.
+        assert_eq!(expand(base, "~tools"), "/etc/ensure-update/~tools");
+    }
+
+    #[test]
+    fn unknown_keys_are_collected() {
+        let path = write(
+            "unknown",
+            r#"
+            colour = true
+            max_ages = "8h"
+
+            [[repository]]
+            path = "repo"
+            post_update = [{ run = "make", pahts = ["src/**"] }]
+            "#,
+        );
+        let (config, unknown) = Config::load_checked(Some(&path)).unwrap();
+        assert_eq!(config.repositories().len(), 1);
+        assert_eq!(
+            unknown,
+            ["colour", "max_ages", "repository.0.post_update.0.pahts"]
+        );
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn a_missing_file_is_only_an_error_if_asked_for() {
+        let path = env::temp_dir().join("ensure-update-test-nonexistent.toml");
+        assert_eq!(
+            Config::load(Some(&path)).unwrap_err().kind(),
+            io::ErrorKind::NotFound
+        );
+    }
+
+    #[test]
+    fn rebase_autostash_always_stashes() {
+        let settings = Settings {
+            strategy: Some(Strategy::RebaseAutostash),
+            ..Default::default()
+        };
+        assert!(settings.autostash());
+
+        let settings = Settings {
+            strategy: Some(Strategy::Rebase),
+            ..Default::default()
+        };
+        assert!(!settings.autostash());
+
+        let settings = Settings {
+            strategy: Some(Strategy::Rebase),
+            autostash: Some(true),
+            ..Default::default()
+        };
+        assert!(settings.autostash());
+    }
+}
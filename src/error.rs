@@ -0,0 +1,400 @@
+use std::{fmt, io, time::Duration};
+
+use jiff::SignedDuration;
+
+/// Everything that can go wrong while updating a repository, sorted by what
+/// the caller might want to do about it.
+#[derive(Debug)]
+pub enum Error {
+    NotADirectory,
+    NotARepository,
+    LoadTable(io::Error),
+    StoreTable(io::Error),
+    /// Git isn't installed, or isn't on the PATH.
+    GitMissing,
+    /// The remote couldn't be reached. Carries git's explanation, and whether
+    /// it's worth trying again (a dropped connection is, a remote that
+    /// doesn't exist isn't).
+    Network {
+        reason: String,
+        transient: bool,
+    },
+    /// The remote wanted credentials we didn't have. Carries git's
+    /// explanation.
+    Authentication(String),
+    /// The update stopped on conflicts. A conflicted rebase is aborted, but a
+    /// conflicted merge is left for the user to sort out.
+    Conflict {
+        aborted: bool,
+    },
+    /// The local branch and its upstream have both moved on.
+    Diverged,
+    /// There's nothing to update from.
+    NoUpstream,
+    /// Git failed for some other reason.
+    Git(String),
+    /// Git took longer than we were willing to wait, and was killed.
+    TimedOut(Duration),
+    /// We were asked to stop (by Ctrl-C, say) and did.
+    Interrupted,
+    Io(io::Error),
+    /// Still failing after retrying.
+    GaveUp {
+        attempts: u32,
+        last: Box<Error>,
+    },
+}
+
+impl Error {
+    /// A short, stable name for the kind of error, for scripts.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Error::NotADirectory => "not-a-directory",
+            Error::NotARepository => "not-a-repository",
+            Error::LoadTable(_) => "load-table",
+            Error::StoreTable(_) => "store-table",
+            Error::GitMissing => "git-missing",
+            Error::Network { .. } => "network",
+            Error::Authentication(_) => "authentication",
+            Error::Conflict { .. } => "conflict",
+            Error::Diverged => "diverged",
+            Error::NoUpstream => "no-upstream",
+            Error::Git(_) => "git",
+            Error::TimedOut(_) => "timed-out",
+            Error::Interrupted => "interrupted",
+            Error::Io(_) => "io",
+            Error::GaveUp { last, .. } => last.kind(),
+        }
+    }
+
+    /// Works out what went wrong from what git had to say about it. Returns
+    /// `None` if git didn't say anything we recognize.
+    ///
+    /// Git doesn't have distinct exit codes for any of this (just about
+    /// everything is 1 or 128), so the messages are all we've got. Every git
+    /// command we run is in English as far as this is concerned, because we
+    /// set LC_ALL=C for it; see [`crate::git::command`].
+    pub fn classify(stderr: &str) -> Option<Error> {
+        // The line that gave the game away doubles as the explanation.
+        let find = |patterns: &[&str]| {
+            stderr.lines().find(|line| {
+                let line = line.to_lowercase();
+                patterns.iter().any(|pattern| line.contains(pattern))
+            })
+        };
+        let has = |patterns: &[&str]| find(patterns).is_some();
+
+        // Order matters here: a failed ssh login also says it "could not read
+        // from remote repository", for instance.
+        let error = if has(&["not a git repository (or any"]) {
+            Error::NotARepository
+        } else if let Some(line) = find(&[
+            "authentication failed",
+            "permission denied (publickey",
+            "could not read username",
+            "could not read password",
+            "terminal prompts disabled",
+            "invalid username or password",
+            "access denied",
+            "http basic: access denied",
+            "host key verification failed",
+        ]) {
+            Error::Authentication(explanation(line))
+        } else if let Some(line) = find(&[
+            "connection reset",
+            "connection timed out",
+            "operation timed out",
+            "timed out after",
+            "temporary failure in name resolution",
+            "the remote end hung up unexpectedly",
+            "early eof",
+            "unexpected disconnect",
+        ]) {
+            Error::Network {
+                reason: explanation(line),
+                transient: true,
+            }
+        } else if let Some(line) = find(&[
+            "could not resolve host",
+            "could not resolve hostname",
+            "name or service not known",
+            "connection refused",
+            "network is unreachable",
+            "no route to host",
+            "unable to access",
+            "does not appear to be a git repository",
+            "could not read from remote repository",
+        ]) {
+            Error::Network {
+                reason: explanation(line),
+                transient: false,
+            }
+        } else if has(&[
+            "conflict (",
+            "automatic merge failed",
+            "could not apply",
+            "resolve all conflicts",
+        ]) {
+            Error::Conflict { aborted: false }
+        } else if has(&[
+            "not possible to fast-forward",
+            "divergent branches",
+            "need to specify how to reconcile",
+        ]) {
+            Error::Diverged
+        } else if has(&[
+            "there is no tracking information",
+            "no upstream configured",
+            "no such ref was fetched",
+            "couldn't find remote ref",
+        ]) {
+            Error::NoUpstream
+        } else {
+            return None;
+        };
+
+        Some(error)
+    }
+
+    /// Failures that might well go away if we try again in a moment.
+    /// Authentication failures and conflicts never do.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Error::Network {
+                transient: true,
+                ..
+            }
+        )
+    }
+
+    /// Errors that say more about the repository we were given than about the
+    /// update itself.
+    pub fn is_invalid(&self) -> bool {
+        match self {
+            Error::NotADirectory | Error::NotARepository => true,
+            Error::GaveUp { last, .. } => last.is_invalid(),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotADirectory => f.write_str("not a directory"),
+            Error::NotARepository => f.write_str("not a git repository"),
+            Error::LoadTable(e) => write!(f, "unable to load the state table: {e}"),
+            Error::StoreTable(e) => write!(f, "unable to store the state table: {e}"),
+            Error::GitMissing => f.write_str("git not found; is it installed and on the PATH?"),
+            Error::Network { reason, .. } => write!(f, "unable to reach the remote: {reason}"),
+            Error::Authentication(reason) => write!(f, "authentication required: {reason}"),
+            Error::Conflict { aborted: true } => {
+                f.write_str("conflicts while updating (the rebase was aborted)")
+            }
+            Error::Conflict { aborted: false } => {
+                f.write_str("conflicts while updating; resolve them and commit")
+            }
+            Error::Diverged => f.write_str("local branch has diverged from its upstream"),
+            Error::NoUpstream => f.write_str("no upstream branch configured"),
+            Error::Git(reason) => f.write_str(reason),
+            Error::TimedOut(timeout) => {
+                let timeout = SignedDuration::try_from(*timeout).unwrap_or(SignedDuration::MAX);
+                write!(f, "timed out after {timeout:#}")
+            }
+            Error::Interrupted => f.write_str("interrupted"),
+            Error::Io(e) => e.fmt(f),
+            Error::GaveUp { attempts, last } => {
+                write!(f, "{last} (gave up after {attempts} attempts)")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::LoadTable(e) | Error::StoreTable(e) | Error::Io(e) => Some(e),
+            Error::GaveUp { last, .. } => Some(last.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        // Which is how waiting on a lock says it was cut short.
+        if e.kind() == io::ErrorKind::Interrupted {
+            Error::Interrupted
+        } else {
+            Error::Io(e)
+        }
+    }
+}
+
+/// Tidies up a line of git's complaint for use in one of ours.
+fn explanation(line: &str) -> String {
+    let line = line.trim();
+    let line = ["fatal:", "error:", "remote:"]
+        .iter()
+        .fold(line, |line, prefix| line.trim_start_matches(prefix).trim());
+    line.trim_end_matches('.').into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn classify(stderr: &str) -> Error {
+        Error::classify(stderr).unwrap_or_else(|| panic!("unclassified: {stderr}"))
+    }
+
+    #[test]
+    fn not_a_repository() {
+        let error =
+            classify("fatal: not a git repository (or any of the parent directories): .git\n");
+        assert!(matches!(error, Error::NotARepository));
+        assert!(error.is_invalid());
+    }
+
+    #[test]
+    fn missing_remote_is_a_permanent_network_error() {
+        let error = classify(
+            "fatal: '/nonexistent' does not appear to be a git repository\n\
+             fatal: Could not read from remote repository.\n\
+             \n\
+             Please make sure you have the correct access rights\n\
+             and the repository exists.\n",
+        );
+        match error {
+            Error::Network { reason, transient } => {
+                assert_eq!(
+                    reason,
+                    "'/nonexistent' does not appear to be a git repository"
+                );
+                assert!(!transient);
+            }
+            error => panic!("{error:?}"),
+        }
+    }
+
+    #[test]
+    fn unresolvable_host_is_a_permanent_network_error() {
+        let error = classify(
+            "fatal: unable to access 'https://nonexistent.invalid/x/': Could not resolve host: nonexistent.invalid\n",
+        );
+        assert!(matches!(
+            error,
+            Error::Network {
+                transient: false,
+                ..
+            }
+        ));
+        assert!(!error.is_transient());
+        assert_eq!(error.kind(), "network");
+    }
+
+    #[test]
+    fn dropped_connections_are_transient() {
+        for stderr in [
+            "error: RPC failed; curl 56 Recv failure: Connection reset by peer\n\
+             fatal: early EOF\n\
+             fatal: fetch-pack: invalid index-pack output\n",
+            "ssh: connect to host github.com port 22: Connection timed out\n\
+             fatal: Could not read from remote repository.\n",
+            "fatal: the remote end hung up unexpectedly\n",
+        ] {
+            let error = classify(stderr);
+            assert!(error.is_transient(), "{stderr}: {error:?}");
+        }
+    }
+
+    #[test]
+    fn authentication_failures() {
+        for stderr in [
+            "fatal: could not read Username for 'https://github.com': terminal prompts disabled\n",
+            "git@github.com: Permission denied (publickey).\n\
+             fatal: Could not read from remote repository.\n",
+            "remote: Invalid username or password.\n\
+             fatal: Authentication failed for 'https://github.com/me/tools.git/'\n",
+            "Host key verification failed.\n\
+             fatal: Could not read from remote repository.\n",
+        ] {
+            let error = classify(stderr);
+            assert!(
+                matches!(error, Error::Authentication(_)),
+                "{stderr}: {error:?}"
+            );
+            assert!(!error.is_transient());
+        }
+
+        let error = classify("git@github.com: Permission denied (publickey).\n");
+        assert_eq!(
+            error.to_string(),
+            "authentication required: git@github.com: Permission denied (publickey)"
+        );
+    }
+
+    #[test]
+    fn conflicts() {
+        for stderr in [
+            "Auto-merging f\n\
+             CONFLICT (content): Merge conflict in f\n\
+             Automatic merge failed; fix conflicts and then commit the result.\n",
+            "error: could not apply 1234567... local change\n\
+             hint: Resolve all conflicts manually, mark them as resolved with\n",
+        ] {
+            assert!(
+                matches!(classify(stderr), Error::Conflict { .. }),
+                "{stderr}"
+            );
+        }
+    }
+
+    #[test]
+    fn diverged() {
+        for stderr in [
+            "fatal: Not possible to fast-forward, aborting.\n",
+            "hint: You have divergent branches and need to specify how to reconcile them.\n\
+             fatal: Need to specify how to reconcile divergent branches.\n",
+        ] {
+            assert!(matches!(classify(stderr), Error::Diverged), "{stderr}");
+        }
+    }
+
+    #[test]
+    fn no_upstream() {
+        for stderr in [
+            "There is no tracking information for the current branch.\n\
+             Please specify which branch you want to merge with.\n",
+            "fatal: couldn't find remote ref refs/heads/nope\n",
+        ] {
+            assert!(matches!(classify(stderr), Error::NoUpstream), "{stderr}");
+        }
+    }
+
+    #[test]
+    fn unrecognized_output_is_left_unclassified() {
+        assert!(Error::classify("").is_none());
+        assert!(
+            Error::classify(
+                "error: Your local changes to the following files would be overwritten by merge:\n"
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn gave_up_takes_after_the_last_error() {
+        let error = Error::GaveUp {
+            attempts: 3,
+            last: Box::new(Error::NotARepository),
+        };
+        assert_eq!(error.kind(), "not-a-repository");
+        assert!(error.is_invalid());
+        assert_eq!(
+            error.to_string(),
+            "not a git repository (gave up after 3 attempts)"
+        );
+    }
+}
@@ -0,0 +1,322 @@
+use std::{
+    env,
+    io::{self, Read, Write},
+    path::Path,
+    process::{Child, Command, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{error::Error, interrupt};
+
+/// Starts a git command.
+pub fn command() -> Command {
+    let mut command = Command::new("git");
+
+    // What git says when it fails is how we tell one failure from another,
+    // so it had better say it in a language we understand.
+    command.env("LC_ALL", "C");
+    command
+}
+
+/// Runs a git command in the given repository and returns its trimmed stdout.
+pub fn output(path: &Path, args: &[&str]) -> Result<String, Error> {
+    let output = command()
+        .arg("-C")
+        .arg(path)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(spawn_error)?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(Error::classify(&stderr)
+            .unwrap_or_else(|| Error::Git(format!("git {} failed", args[0]))));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().into())
+}
+
+/// How often we check on a running command, to see whether it's overstayed
+/// its welcome or we've been interrupted.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How long a command gets to exit after being asked nicely, before it's
+/// killed outright.
+const TERMINATE_GRACE: Duration = Duration::from_secs(2);
+
+/// What a finished command left behind.
+pub struct Finished {
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+/// Runs a git command to completion, keeping a copy of its stderr. With
+/// `tee`, stderr is passed through as it goes, too. Stdout is collected only
+/// if the caller asked for it to be piped.
+///
+/// A `non_interactive` command runs in a process group of its own, so that
+/// when time's up or we're interrupted, whatever it started (ssh, say) goes
+/// down with it. An interactive one can't: anything that asks for a password
+/// has to be in the terminal's foreground group to read the answer. So only
+/// git itself is stopped, and what it started is left to notice it's gone
+/// (though Ctrl-C at the terminal reaches all of them anyway).
+pub fn run(
+    command: &mut Command,
+    tee: bool,
+    timeout: Option<Duration>,
+    non_interactive: bool,
+) -> Result<Finished, Error> {
+    #[cfg(unix)]
+    if non_interactive {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+
+    let mut child = command
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(spawn_error)?;
+
+    let stdout = child.stdout.take().map(|mut pipe| {
+        thread::spawn(move || {
+            let mut stdout = Vec::new();
+            let _ = pipe.read_to_end(&mut stdout);
+            stdout
+        })
+    });
+
+    let mut pipe = child.stderr.take().expect("stderr is piped");
+    let stderr = thread::spawn(move || {
+        let mut stderr = Vec::new();
+        let mut buf = [0; 4096];
+        loop {
+            let n = match pipe.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+
+            // Progress meters and the like are meant to be seen as they
+            // happen, so we don't wait for a whole line.
+            if tee {
+                let mut out = io::stderr().lock();
+                let _ = out.write_all(&buf[..n]);
+                let _ = out.flush();
+            }
+            stderr.extend_from_slice(&buf[..n]);
+        }
+        stderr
+    });
+
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+
+        let error = if interrupt::requested() {
+            Error::Interrupted
+        } else if let Some(timeout) = timeout.filter(|timeout| start.elapsed() >= *timeout) {
+            Error::TimedOut(timeout)
+        } else {
+            thread::sleep(POLL_INTERVAL);
+            continue;
+        };
+
+        terminate(&mut child, non_interactive);
+        return Err(error);
+    };
+
+    Ok(Finished {
+        status,
+        stdout: stdout
+            .map(|reader| reader.join().unwrap_or_default())
+            .unwrap_or_default(),
+        stderr: stderr.join().unwrap_or_default(),
+    })
+}
+
+/// Stops a command and whatever it started, giving it a moment to clean up
+/// after itself (git removes its lock files on SIGTERM) before killing it.
+#[cfg(unix)]
+fn terminate(child: &mut Child, grouped: bool) {
+    let Ok(pid) = libc::pid_t::try_from(child.id()) else {
+        return;
+    };
+    let target = if grouped { -pid } else { pid };
+
+    unsafe {
+        libc::kill(target, libc::SIGTERM);
+    }
+
+    let start = Instant::now();
+    while start.elapsed() < TERMINATE_GRACE {
+        if let Ok(Some(_)) = child.try_wait() {
+            // The leader is gone, but stragglers in its group needn't be.
+            if grouped {
+                unsafe {
+                    libc::kill(target, libc::SIGKILL);
+                }
+            }
+            return;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    unsafe {
+        libc::kill(target, libc::SIGKILL);
+    }
+    let _ = child.wait();
+}
+
+#[cfg(not(unix))]
+fn terminate(child: &mut Child, _grouped: bool) {
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// Sets a command up so that neither git nor anything it runs will stop to
+/// ask for anything: credentials, passphrases, whether to trust a host key.
+/// Whatever it would have asked for, it fails without instead, and says so.
+pub fn non_interactive(command: &mut Command, repository: &Path) {
+    command.env("GIT_TERMINAL_PROMPT", "0");
+
+    // An empty askpass is no askpass at all, as far as git is concerned, and
+    // it stops git falling back on SSH_ASKPASS.
+    command.env("GIT_ASKPASS", "").env("SSH_ASKPASS", "");
+
+    // Whatever ssh command would otherwise have been used, we want it in
+    // batch mode, which fails rather than prompting. Git looks for it in
+    // GIT_SSH_COMMAND, then GIT_SSH, then core.sshCommand; GIT_SSH names a
+    // program rather than a command line, so there's nothing we can add to
+    // it, and setting GIT_SSH_COMMAND would override it, so we leave it be.
+    let ssh = match env::var("GIT_SSH_COMMAND")
+        .ok()
+        .filter(|ssh| !ssh.is_empty())
+    {
+        Some(ssh) => Some(ssh),
+        None if env::var_os("GIT_SSH").is_some_and(|ssh| !ssh.is_empty()) => None,
+        None => Some(
+            output(repository, &["config", "core.sshCommand"])
+                .ok()
+                .filter(|ssh| !ssh.is_empty())
+                .unwrap_or_else(|| String::from("ssh")),
+        ),
+    };
+    if let Some(ssh) = ssh {
+        let variant = env::var("GIT_SSH_VARIANT")
+            .ok()
+            .or_else(|| output(repository, &["config", "ssh.variant"]).ok());
+        if let Some(batch) = batch_option(&ssh, variant.as_deref()) {
+            command.env("GIT_SSH_COMMAND", format!("{ssh} {batch}"));
+        }
+    }
+
+    // Credential helpers that know better than to pop up a window when told
+    // not to. Config passed through the environment is numbered, so ours goes
+    // after anybody else's.
+    let count: usize = env::var("GIT_CONFIG_COUNT")
+        .ok()
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(0);
+    command
+        .env(format!("GIT_CONFIG_KEY_{count}"), "credential.interactive")
+        .env(format!("GIT_CONFIG_VALUE_{count}"), "false")
+        .env("GIT_CONFIG_COUNT", (count + 1).to_string())
+        .env("GCM_INTERACTIVE", "never");
+}
+
+/// The option that stops an ssh command from prompting, if we know it.
+///
+/// Different ssh clients want different options, so we work out which one
+/// this is the same way git does (see ssh.variant in git-config(1)), short of
+/// running it to find out. Anything we don't recognize is left alone; an
+/// option it doesn't understand would break it outright.
+fn batch_option(ssh: &str, variant: Option<&str>) -> Option<&'static str> {
+    let variant = match variant.map(str::to_lowercase) {
+        Some(variant) if variant != "auto" => variant,
+        _ => {
+            let program = ssh.split_whitespace().next()?;
+            let program = program.rsplit(['/', '\\']).next()?.to_lowercase();
+            program.strip_suffix(".exe").unwrap_or(&program).to_owned()
+        }
+    };
+
+    match variant.as_str() {
+        "ssh" => Some("-o BatchMode=yes"),
+        "plink" | "putty" | "tortoiseplink" => Some("-batch"),
+        _ => None,
+    }
+}
+
+/// Git being missing is worth calling out, since it's the one thing we can't
+/// do anything at all without.
+pub fn spawn_error(e: io::Error) -> Error {
+    if e.kind() == io::ErrorKind::NotFound {
+        Error::GitMissing
+    } else {
+        Error::Io(e)
+    }
+}
+
+/// Returns the url of the given remote.
+pub fn remote_url(path: &Path, remote: &str) -> Result<String, Error> {
+    let key = format!("remote.{remote}.url");
+    output(path, &["config", "--get", &key]).map_err(|e| match e {
+        Error::Git(_) => Error::Git(format!("no {remote} remote configured")),
+        e => e,
+    })
+}
+
+/// Returns the commit HEAD points at, if it points at anything at all.
+pub fn head(path: &Path) -> Option<String> {
+    output(path, &["rev-parse", "--verify", "--quiet", "HEAD"]).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn openssh_gets_batch_mode() {
+        assert_eq!(batch_option("ssh", None), Some("-o BatchMode=yes"));
+        assert_eq!(
+            batch_option("/usr/bin/ssh -i ~/.ssh/deploy", None),
+            Some("-o BatchMode=yes")
+        );
+        assert_eq!(
+            batch_option("ssh.exe", Some("auto")),
+            Some("-o BatchMode=yes")
+        );
+    }
+
+    #[test]
+    fn plink_gets_its_own_option() {
+        assert_eq!(batch_option("plink", None), Some("-batch"));
+        assert_eq!(batch_option("C:\\PuTTY\\plink.exe", None), Some("-batch"));
+        assert_eq!(batch_option("TortoisePlink.exe", None), Some("-batch"));
+    }
+
+    #[test]
+    fn the_configured_variant_wins() {
+        assert_eq!(
+            batch_option("my-ssh-wrapper", Some("ssh")),
+            Some("-o BatchMode=yes")
+        );
+        assert_eq!(
+            batch_option("my-ssh-wrapper", Some("plink")),
+            Some("-batch")
+        );
+        assert_eq!(batch_option("ssh", Some("simple")), None);
+    }
+
+    #[test]
+    fn unknown_commands_are_left_alone() {
+        assert_eq!(batch_option("my-ssh-wrapper --flag", None), None);
+        assert_eq!(batch_option("", None), None);
+    }
+}
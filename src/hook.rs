@@ -0,0 +1,233 @@
+use std::{
+    fmt, io,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use serde::{Deserialize, Deserializer, Serialize, de};
+
+use crate::git;
+
+/// A command to run around an update, from the config file. It's run by the
+/// shell, in the repository.
+///
+/// Either just the command, or a table with the command and the paths it
+/// cares about, as git pathspec globs:
+///
+/// ```toml
+/// post_update = [
+///     "notify-send 'tools updated'",
+///     { run = "cargo install --path .", paths = ["src/**", "Cargo.*"] },
+/// ]
+/// ```
+#[derive(Clone, Debug)]
+pub struct Hook {
+    pub run: String,
+    pub paths: Vec<String>,
+}
+
+impl Hook {
+    /// Works out whether the hook should run for an update from `old_head`
+    /// to `new_head`, and if so, why. A hook without paths always runs; one
+    /// with paths runs if any file matching them was changed.
+    pub fn trigger(
+        &self,
+        repository: &Path,
+        old_head: Option<&str>,
+        new_head: &str,
+    ) -> Option<String> {
+        if self.paths.is_empty() {
+            return Some(String::from("new commits"));
+        }
+
+        for pattern in &self.paths {
+            // Git already knows how to match paths, and does it the same way
+            // it does everywhere else, so ** and friends mean what they do in
+            // .gitignore.
+            let pathspec = format!(":(glob){pattern}");
+            let changed = match old_head {
+                Some(old_head) => git::output(
+                    repository,
+                    &[
+                        "diff",
+                        "--name-only",
+                        "--no-renames",
+                        old_head,
+                        new_head,
+                        "--",
+                        &pathspec,
+                    ],
+                ),
+                // Out of nothing came everything.
+                None => git::output(
+                    repository,
+                    &["ls-tree", "-r", "--name-only", new_head, "--", &pathspec],
+                ),
+            };
+
+            // If we can't tell, we'd rather run a hook needlessly than miss
+            // one that mattered.
+            let changed = match changed {
+                Ok(changed) => changed,
+                Err(e) => return Some(format!("couldn't tell what changed: {e}")),
+            };
+
+            let mut files = changed.lines();
+            if let Some(file) = files.next() {
+                return Some(match files.count() {
+                    0 => format!("{file} matches {pattern}"),
+                    n => format!("{file} and {n} more match {pattern}"),
+                });
+            }
+        }
+
+        None
+    }
+}
+
+impl<'de> Deserialize<'de> for Hook {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = Hook;
+
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("a command, or a table with `run` and `paths`")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Hook, E> {
+                Ok(Hook {
+                    run: v.into(),
+                    paths: Vec::new(),
+                })
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<Hook, A::Error> {
+                Full::deserialize(de::value::MapAccessDeserializer::new(map)).map(|full| Hook {
+                    run: full.run,
+                    paths: full.paths,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+/// The hooks configured for a repository.
+#[derive(Clone, Debug, Default)]
+pub struct Hooks {
+    /// Run before an update; any one of them failing calls it off.
+    pub pre_update: Vec<Hook>,
+    /// Run after an update that moved HEAD.
+    pub post_update: Vec<Hook>,
+}
+
+/// The table form of a hook.
+#[derive(Deserialize)]
+struct Full {
+    run: String,
+    #[serde(default)]
+    paths: Vec<String>,
+}
+
+/// How a hook went.
+#[derive(Debug, Serialize)]
+pub struct Ran {
+    pub stage: &'static str,
+    pub command: String,
+    pub reason: String,
+    pub success: bool,
+    pub exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Where a hook's output goes: straight through, or into the buffers an
+/// update collects when its output is to be printed in one piece. As with
+/// git's, stdout is only shown with --verbose.
+pub enum Output<'a> {
+    Inherit { verbose: bool, json: bool },
+    Capture(&'a mut (Vec<u8>, Vec<u8>)),
+}
+
+impl<'a> Output<'a> {
+    pub fn new(captured: Option<&'a mut (Vec<u8>, Vec<u8>)>, verbose: bool, json: bool) -> Self {
+        match captured {
+            Some(captured) => Output::Capture(captured),
+            None => Output::Inherit { verbose, json },
+        }
+    }
+}
+
+impl Hook {
+    /// Runs the hook to completion with the given variables set. Stdin is
+    /// left alone unless nobody's around to use it.
+    pub fn run(
+        &self,
+        stage: &'static str,
+        reason: String,
+        repository: &Path,
+        env: &[(&str, &str)],
+        non_interactive: bool,
+        output: Output,
+    ) -> Ran {
+        let mut command = shell(&self.run);
+        command.current_dir(repository).envs(env.iter().copied());
+        if non_interactive {
+            command.stdin(Stdio::null());
+        }
+
+        let status = match output {
+            Output::Inherit { verbose: false, .. } => command.stdout(Stdio::null()).status(),
+            // Stdout belongs to the JSON report, so the hook gets stderr.
+            Output::Inherit { json: true, .. } => command.stdout(io::stderr()).status(),
+            Output::Inherit { json: false, .. } => command.status(),
+            Output::Capture((stdout, stderr)) => command.output().map(|output| {
+                stdout.extend(&output.stdout);
+                stderr.extend(&output.stderr);
+                output.status
+            }),
+        };
+
+        let (success, exit_code, error) = match status {
+            Ok(status) => (status.success(), status.code(), None),
+            Err(e) => (false, None, Some(e.to_string())),
+        };
+        Ran {
+            stage,
+            command: self.run.clone(),
+            reason,
+            success,
+            exit_code,
+            error,
+        }
+    }
+}
+
+impl fmt::Display for Ran {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} hook `{}` ", self.stage, self.command)?;
+        match (&self.error, self.exit_code) {
+            _ if self.success => f.write_str("succeeded"),
+            (Some(error), _) => write!(f, "couldn't be run: {error}"),
+            (None, Some(code)) => write!(f, "failed (exit {code})"),
+            (None, None) => f.write_str("was killed"),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn shell(script: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(script);
+    command
+}
+
+#[cfg(not(unix))]
+fn shell(script: &str) -> Command {
+    let mut command = Command::new("cmd");
+    command.arg("/C").arg(script);
+    command
+}
@@ -0,0 +1,177 @@
+use std::{fmt, path::Path};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{error::Error, git};
+
+/// How much to say about what an update brought in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum Level {
+    /// Nothing; just whether it was updated.
+    #[default]
+    None,
+    /// How many commits, how many lines changed, and any new tags.
+    Summary,
+    /// As summary, plus each commit's subject and author.
+    Commits,
+    /// As commits, plus lines changed per file.
+    Files,
+}
+
+/// What an update brought in: everything between HEAD before and HEAD after.
+#[derive(Debug, Serialize)]
+pub struct Incoming {
+    pub commits: usize,
+    pub files_changed: usize,
+    pub insertions: u64,
+    pub deletions: u64,
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub log: Vec<Commit>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<File>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Commit {
+    pub hash: String,
+    pub author: String,
+    pub subject: String,
+}
+
+/// Lines changed in one file. Binary files don't have lines, so they don't
+/// get counts.
+#[derive(Debug, Serialize)]
+pub struct File {
+    pub path: String,
+    pub insertions: Option<u64>,
+    pub deletions: Option<u64>,
+}
+
+/// A long list of commits is just noise in a terminal; the JSON report gets
+/// the lot.
+const MAX_LISTED: usize = 20;
+
+impl Incoming {
+    /// Works out what changed between two commits, in as much detail as was
+    /// asked for. Returns `None` at level `None`.
+    pub fn collect(
+        repository: &Path,
+        old_head: &str,
+        new_head: &str,
+        level: Level,
+    ) -> Result<Option<Incoming>, Error> {
+        if level == Level::None {
+            return Ok(None);
+        }
+
+        let range = format!("{old_head}..{new_head}");
+        let log = git::output(
+            repository,
+            &["log", "--format=%h%x00%an%x00%s", &range, "--"],
+        )?;
+        let log: Vec<_> = log
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(3, '\0');
+                Some(Commit {
+                    hash: fields.next()?.into(),
+                    author: fields.next()?.into(),
+                    subject: fields.next()?.into(),
+                })
+            })
+            .collect();
+
+        // Numstat rather than stat, because numbers are what we're after;
+        // binary files come out as "-".
+        let numstat = git::output(repository, &["diff", "--numstat", old_head, new_head])?;
+        let files: Vec<_> = numstat
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(3, '\t');
+                let insertions = fields.next()?.parse().ok();
+                let deletions = fields.next()?.parse().ok();
+                Some(File {
+                    path: fields.next()?.into(),
+                    insertions,
+                    deletions,
+                })
+            })
+            .collect();
+
+        // The tags that came in are the ones we can reach now and couldn't
+        // before.
+        let merged = format!("--merged={new_head}");
+        let no_merged = format!("--no-merged={old_head}");
+        let tags = git::output(repository, &["tag", "--list", &merged, &no_merged])?;
+
+        Ok(Some(Incoming {
+            commits: log.len(),
+            files_changed: files.len(),
+            insertions: files.iter().filter_map(|file| file.insertions).sum(),
+            deletions: files.iter().filter_map(|file| file.deletions).sum(),
+            tags: tags.lines().map(String::from).collect(),
+            log: if level >= Level::Commits {
+                log
+            } else {
+                Vec::new()
+            },
+            files: if level >= Level::Files {
+                files
+            } else {
+                Vec::new()
+            },
+        }))
+    }
+}
+
+/// Renders the report as indented lines, to go under the line saying the
+/// repository was updated.
+impl fmt::Display for Incoming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |n: usize, what: &str| {
+            if n == 1 {
+                format!("{n} {what}")
+            } else {
+                format!("{n} {what}s")
+            }
+        };
+        write!(
+            f,
+            "  {}, {} changed, +{} -{}",
+            plural(self.commits, "commit"),
+            plural(self.files_changed, "file"),
+            self.insertions,
+            self.deletions,
+        )?;
+        if !self.tags.is_empty() {
+            write!(f, "\n  new tags: {}", self.tags.join(", "))?;
+        }
+
+        for commit in self.log.iter().take(MAX_LISTED) {
+            write!(
+                f,
+                "\n    {} {} ({})",
+                commit.hash, commit.subject, commit.author
+            )?;
+        }
+        if self.log.len() > MAX_LISTED {
+            write!(f, "\n    ...and {} more", self.log.len() - MAX_LISTED)?;
+        }
+
+        for file in self.files.iter().take(MAX_LISTED) {
+            match (file.insertions, file.deletions) {
+                (Some(insertions), Some(deletions)) => {
+                    write!(f, "\n    {} | +{insertions} -{deletions}", file.path)?
+                }
+                _ => write!(f, "\n    {} | binary", file.path)?,
+            }
+        }
+        if self.files.len() > MAX_LISTED {
+            write!(f, "\n    ...and {} more", self.files.len() - MAX_LISTED)?;
+        }
+
+        Ok(())
+    }
+}
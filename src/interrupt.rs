@@ -0,0 +1,46 @@
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+static REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Takes over Ctrl-C, so that an interrupted update can be cleaned up after
+/// (and the table stored) instead of leaving things wherever they fell.
+#[cfg(unix)]
+pub fn install() {
+    extern "C" fn handle(_: libc::c_int) {
+        REQUESTED.store(true, Ordering::SeqCst);
+    }
+
+    let handler = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        libc::signal(libc::SIGINT, handler);
+        libc::signal(libc::SIGTERM, handler);
+    }
+}
+
+/// Without signals to catch, Ctrl-C does whatever it always did.
+#[cfg(not(unix))]
+pub fn install() {}
+
+/// Sleeps for the given time, or until we're asked to stop, whichever comes
+/// first.
+pub fn sleep(duration: Duration) {
+    let start = Instant::now();
+    while !requested() {
+        let left = duration.saturating_sub(start.elapsed());
+        if left.is_zero() {
+            return;
+        }
+        thread::sleep(left.min(POLL_INTERVAL));
+    }
+}
+
+/// Whether we've been asked to stop.
+pub fn requested() -> bool {
+    REQUESTED.load(Ordering::SeqCst)
+}
@@ -0,0 +1,136 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use clap::ValueEnum;
+
+use crate::{error::Error, git};
+
+/// Determines how a repository is identified in the timestamp table.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum KeyMode {
+    /// The canonicalized absolute path of the repository.
+    #[default]
+    Path,
+    /// The url of the repository's origin remote.
+    Remote,
+    /// The hash of the repository's root commit.
+    RootCommit,
+}
+
+/// Resolves the key under which a repository's timestamp is stored.
+pub fn resolve(repository: &str, mode: KeyMode) -> Result<String, Error> {
+    let path = Path::new(repository);
+
+    // This is only legal for directories; a repository MUST
+    // be a folder.
+    if !path.is_dir() {
+        return Err(Error::NotADirectory);
+    }
+
+    match mode {
+        KeyMode::Path => {
+            // Canonicalizing resolves symlinks and relative components, so
+            // "~/src/tools" and "./tools" (run from ~/src) end up as the same
+            // entry while "~/work/tools" and "~/personal/tools" do not. Lossy
+            // conversion is still a compromise, but a much smaller one than
+            // the basename ever was.
+            let path = fs::canonicalize(path)?;
+            Ok(path.to_string_lossy().into())
+        }
+
+        KeyMode::Remote => git::remote_url(path, "origin"),
+
+        // A repository can have more than one root (think merged histories),
+        // but rev-list always prints them in the same order, so the last one
+        // is as good a choice as any and a stable one besides.
+        KeyMode::RootCommit => {
+            let roots = git::output(path, &["rev-list", "--max-parents=0", "HEAD"])?;
+            roots
+                .lines()
+                .last()
+                .map(String::from)
+                .ok_or_else(|| Error::Git(String::from("no commits found")))
+        }
+    }
+}
+
+/// Moves a timestamp recorded under the repository's legacy (basename) key
+/// over to its new key.
+///
+/// Older versions of this program keyed the table by directory name alone,
+/// and there is no way to turn "tools" back into a full path, so tables are
+/// migrated one repository at a time as each one is encountered. Returns the
+/// legacy key if the table was modified.
+pub fn migrate<T>(table: &mut HashMap<String, T>, repository: &str, key: &str) -> Option<String> {
+    if table.contains_key(key) {
+        return None;
+    }
+
+    let legacy = legacy_name(repository)?;
+    let value = table.remove(&legacy)?;
+    table.insert(key.into(), value);
+    Some(legacy)
+}
+
+fn legacy_name(repository: &str) -> Option<String> {
+    // In theory, I'm not best pleased with this solution, because it means
+    // that unequal paths could be treated equally if they map to the same
+    // lossy string. However, that's exactly what the old key looked like, so
+    // that's what we need to look for.
+    let name = Path::new(repository).file_name()?;
+    Some(name.to_string_lossy().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(entries: &[(&str, u32)]) -> HashMap<String, u32> {
+        entries.iter().map(|&(k, v)| (k.to_owned(), v)).collect()
+    }
+
+    #[test]
+    fn migrate_moves_legacy_entry_to_new_key() {
+        let mut table = table(&[("tools", 1), ("other", 2)]);
+        let legacy = migrate(&mut table, "/home/user/src/tools", "/home/user/src/tools");
+        assert_eq!(legacy.as_deref(), Some("tools"));
+        assert_eq!(table.get("/home/user/src/tools"), Some(&1));
+        assert!(!table.contains_key("tools"));
+        assert_eq!(table.get("other"), Some(&2));
+    }
+
+    #[test]
+    fn migrate_leaves_existing_key_alone() {
+        let mut table = table(&[("tools", 1), ("/home/user/src/tools", 5)]);
+        assert_eq!(
+            migrate(&mut table, "/home/user/src/tools", "/home/user/src/tools"),
+            None
+        );
+        assert_eq!(table.get("/home/user/src/tools"), Some(&5));
+        assert_eq!(table.get("tools"), Some(&1));
+    }
+
+    #[test]
+    fn migrate_without_legacy_entry_does_nothing() {
+        let mut table = table(&[("other", 2)]);
+        assert_eq!(
+            migrate(&mut table, "/home/user/src/tools", "/home/user/src/tools"),
+            None
+        );
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn migrate_uses_the_directory_name_of_relative_paths() {
+        let mut table = table(&[("tools", 1)]);
+        let legacy = migrate(&mut table, "src/tools/", "git@example.com:me/tools.git");
+        assert_eq!(legacy.as_deref(), Some("tools"));
+        assert_eq!(table.get("git@example.com:me/tools.git"), Some(&1));
+    }
+
+    #[test]
+    fn migrate_ignores_paths_without_a_name() {
+        let mut table = table(&[("..", 1)]);
+        assert_eq!(migrate(&mut table, "..", "/home/user"), None);
+        assert_eq!(table.get(".."), Some(&1));
+    }
+}
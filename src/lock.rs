@@ -0,0 +1,197 @@
+use std::{
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, Read, Seek, Write},
+    path::{Path, PathBuf},
+    process, thread,
+    time::{Duration, Instant},
+};
+
+use directories::ProjectDirs;
+
+use crate::interrupt;
+
+/// How long to sleep between attempts to take a lock someone else is holding.
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// An advisory lock, held for as long as this value lives.
+///
+/// Locks are held by the operating system (flock(2) on unix), on files that
+/// stay put once created, so a holder that crashes lets go of its lock on its
+/// way out and there's nothing stale to break. The holder's process id goes
+/// in the file, but only so that we can say who's got it.
+#[derive(Debug)]
+pub struct Lock {
+    file: File,
+}
+
+impl Lock {
+    /// The lock guarding the state table.
+    pub fn table() -> io::Result<PathBuf> {
+        Ok(lock_dir()?.join("table.lock"))
+    }
+
+    /// The lock guarding updates to a repository, by its key.
+    pub fn repository(key: &str) -> io::Result<PathBuf> {
+        Ok(lock_dir()?.join(format!("repository-{:016x}.lock", fnv1a(key))))
+    }
+
+    /// Takes the lock if it's free. If it isn't, returns the process id of
+    /// the holder (or zero, if we couldn't tell).
+    pub fn try_acquire(path: &Path) -> io::Result<Result<Lock, u32>> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        match file.try_lock() {
+            Ok(()) => (),
+            Err(TryLockError::WouldBlock) => {
+                // The holder may not have got round to writing its pid yet,
+                // or the platform may not let us read a locked file at all.
+                let mut contents = String::new();
+                let _ = file.read_to_string(&mut contents);
+                return Ok(Err(contents.trim().parse().unwrap_or(0)));
+            }
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+
+        // Whatever's in there was left by a previous holder.
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", process::id())?;
+        Ok(Ok(Lock { file }))
+    }
+
+    /// Takes the lock, waiting for it if need be. Gives up after `timeout`,
+    /// if there is one.
+    pub fn acquire(path: &Path, timeout: Option<Duration>) -> io::Result<Lock> {
+        let start = Instant::now();
+        loop {
+            let pid = match Self::try_acquire(path)? {
+                Ok(lock) => return Ok(lock),
+                Err(pid) => pid,
+            };
+
+            if interrupt::requested() {
+                return Err(io::Error::new(
+                    io::ErrorKind::Interrupted,
+                    format!("interrupted waiting for {}", path.display()),
+                ));
+            }
+
+            if timeout.is_some_and(|timeout| start.elapsed() >= timeout) {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "timed out waiting for {} (held by pid {pid})",
+                        path.display()
+                    ),
+                ));
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // The file itself stays: deleting it would let somebody lock a fresh
+        // one while somebody else is still waiting on this one. Emptying it
+        // is enough to say nobody's home.
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
+
+fn lock_dir() -> io::Result<PathBuf> {
+    let dirs = ProjectDirs::from("", "hack-commons", "ensure-update")
+        .ok_or_else(|| io::Error::other("unable to locate data directory"))?;
+    let dir = dirs.data_local_dir().join("locks");
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// A stable hash, so that every version of this program agrees on where a
+/// repository's lock lives.
+fn fnv1a(s: &str) -> u64 {
+    s.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Checks whether a process is still running.
+#[cfg(unix)]
+pub fn alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+
+    // Signal 0 doesn't actually deliver anything, but still checks whether
+    // the process exists. EPERM means it does, but belongs to someone else.
+    unsafe {
+        libc::kill(pid, 0) == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+    }
+}
+
+/// Checks whether a process is still running.
+///
+/// Without a cheap way to tell, we assume it is, unless it's us. The worst
+/// case is a stale lock that has to be cleaned up by hand.
+#[cfg(not(unix))]
+pub fn alive(pid: u32) -> bool {
+    pid != process::id()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use super::*;
+
+    fn path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("ensure-update-test-{}-{name}.lock", process::id()))
+    }
+
+    #[test]
+    fn a_held_lock_names_its_holder() {
+        let path = path("held");
+        let lock = Lock::try_acquire(&path).unwrap().unwrap();
+        assert_eq!(
+            Lock::try_acquire(&path).unwrap().unwrap_err(),
+            process::id()
+        );
+
+        drop(lock);
+        let lock = Lock::try_acquire(&path).unwrap();
+        assert!(lock.is_ok());
+        drop(lock);
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn leftovers_from_a_dead_holder_are_not_a_lock() {
+        let path = path("leftover");
+        fs::write(&path, "999999999").unwrap();
+        let lock = Lock::try_acquire(&path).unwrap().unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            process::id().to_string()
+        );
+
+        drop(lock);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "");
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn acquire_gives_up_after_its_timeout() {
+        let path = path("timeout");
+        let _lock = Lock::try_acquire(&path).unwrap().unwrap();
+        let error = Lock::acquire(&path, Some(Duration::from_millis(100))).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        let _ = fs::remove_file(&path);
+    }
+}
@@ -1,22 +1,65 @@
+mod age;
+mod background;
+mod check;
+mod config;
+mod error;
+mod git;
+mod hook;
+mod incoming;
+mod interrupt;
+mod key;
+mod lock;
+mod pool;
+mod record;
+mod report;
+mod retry;
+mod state;
+mod status;
+mod strategy;
+mod upstream;
+mod worktree;
+
 use std::{
-    collections::HashMap,
-    io,
-    path::Path,
+    ffi::{OsStr, OsString},
+    fs,
+    io::{self, BufRead, IsTerminal, Write},
+    iter, mem,
+    path::{Path, PathBuf},
     process::{self, Command, Stdio},
+    sync::Mutex,
+    time::{Duration, Instant},
 };
 
-use abseil::Provider;
-use clap::Parser;
-use jiff::{SignedDuration, Timestamp};
+use age::{MaxAge, Timeout};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use config::{Config, Issue, Settings};
+use error::Error;
+use hook::Hooks;
+use incoming::Incoming;
+use jiff::{SignedDuration, Timestamp, tz::TimeZone};
+use key::KeyMode;
+use lock::Lock;
+use record::{AttemptResult, Record, Update};
+use report::{Entry, Exit, Format, Report};
+use state::{Changes, State, Table};
+use strategy::Strategy;
+use upstream::Upstream;
+use worktree::Blocker;
 
 #[derive(Debug, Parser)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Opts {
-    // a git repository to update
-    repository: String,
+    #[command(subcommand)]
+    action: Option<Action>,
+
+    // git repositories to update; pass - to read paths from stdin, or nothing
+    // at all to update every repository in the config file
+    repositories: Vec<String>,
 
-    // how long ago can the last update be before we trigger another
-    #[arg(default_value_t = 4)]
-    max_age: i32,
+    // how long ago can the last update be before we trigger another (e.g.
+    // 30m, 4h, 1d12h, 1w; a plain number is read as hours) [default: 4h]
+    #[arg(short = 'a', long, allow_negative_numbers = true)]
+    max_age: Option<MaxAge>,
 
     // ignore last update time
     #[arg(short, long)]
@@ -25,102 +68,1542 @@ struct Opts {
     // print command output
     #[arg(long)]
     verbose: bool,
+
+    // how to report what happened
+    #[arg(long, value_enum, default_value_t)]
+    output: Format,
+
+    // exit with a code that says what happened (updated, skipped, which kind
+    // of failure...) instead of just 0 or 1
+    #[arg(long)]
+    detailed_exit_codes: bool,
+
+    // how much to say about what each update brought in: none, summary
+    // (commit and line counts, new tags), commits (plus each commit's
+    // subject and author), or files (plus lines changed per file)
+    #[arg(long, value_enum, default_value_t)]
+    changes: incoming::Level,
+
+    // how to identify the repository in the timestamp table
+    #[arg(long, value_enum, default_value_t)]
+    key: KeyMode,
+
+    // how many repositories to update at once
+    #[arg(short, long, default_value_t = 1)]
+    jobs: usize,
+
+    // how many repositories sharing a remote host to update at once
+    #[arg(long, default_value_t = 2)]
+    jobs_per_host: usize,
+
+    // how to bring a repository up to date
+    #[arg(short, long, value_enum)]
+    strategy: Option<Strategy>,
+
+    // stash uncommitted changes before updating (and restore them after)
+    // instead of skipping the repository
+    #[arg(long)]
+    autostash: bool,
+
+    // how many times to retry an update that failed for reasons that might
+    // not last, like a dropped connection [default: 2]
+    #[arg(long)]
+    retries: Option<u32>,
+
+    // never let git stop to ask for credentials or confirm a host key; on by
+    // default when stdin isn't a terminal
+    #[arg(long, conflicts_with = "interactive")]
+    non_interactive: bool,
+
+    // let git prompt, even when stdin isn't a terminal
+    #[arg(long)]
+    interactive: bool,
+
+    // kill any git command that takes longer than this (e.g. 30s, 5m; a plain
+    // number is read as seconds)
+    #[arg(long)]
+    timeout: Option<Timeout>,
+
+    // say what would be done, and why, without doing any of it: nothing is
+    // run, and the table is left as it is
+    #[arg(long)]
+    dry_run: bool,
+
+    // wait for another update of the same repository to finish, instead of
+    // skipping it
+    #[arg(long)]
+    wait_for_lock: bool,
+
+    // permit the reset-to-upstream strategy, which discards local commits and
+    // uncommitted changes
+    #[arg(long)]
+    allow_reset: bool,
+
+    /// read configuration from this file instead of the default location
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    // update in the background and return right away; the outcome is
+    // reported the next time around
+    #[arg(long)]
+    background: bool,
+
+    // marks the detached process started by --background
+    #[arg(long, hide = true)]
+    background_child: bool,
+
+    // run the command anyway if the update fails
+    #[arg(long, requires = "exec")]
+    always_run: bool,
+
+    // a command to run once the update is done, given after --
+    #[arg(last = true, value_name = "COMMAND")]
+    exec: Vec<String>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Action {
+    /// manage the configuration file
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
+    /// say whether a repository is due for an update, without updating it;
+    /// exits 0 if it's fresh and 1 if it's due
+    Check {
+        /// the git repository to check
+        repository: String,
+
+        /// how long ago can the last update be before we trigger another
+        #[arg(short = 'a', long, allow_negative_numbers = true)]
+        max_age: Option<MaxAge>,
+
+        /// also ask the remote whether anything new has turned up since the
+        /// last fetch (one quick round trip)
+        #[arg(long)]
+        remote: bool,
+
+        /// how the repository is identified in the timestamp table
+        #[arg(long, value_enum, default_value_t)]
+        key: KeyMode,
+
+        /// print nothing at all; the exit code says it all
+        #[arg(short, long)]
+        quiet: bool,
+    },
+
+    /// show when each repository was last updated, and when it's next due
+    Status {
+        /// print the table as JSON
+        #[arg(long)]
+        json: bool,
+
+        /// judge freshness by this max age instead of the configured one
+        #[arg(short = 'a', long, allow_negative_numbers = true)]
+        max_age: Option<MaxAge>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigAction {
+    /// check the configuration file for unknown keys and bad paths
+    Check,
+}
+
+impl Opts {
+    /// Settings given on the command line, which take precedence over
+    /// anything in the config file.
+    fn settings(&self) -> Settings {
+        Settings {
+            max_age: self.max_age,
+            strategy: self.strategy,
+            autostash: self.autostash.then_some(true),
+            allow_reset: self.allow_reset.then_some(true),
+            verbose: self.verbose.then_some(true),
+            retries: self.retries,
+            timeout: self.timeout,
+            ..Default::default()
+        }
+    }
+
+    /// Arguments for a background process updating the given repositories
+    /// on our behalf. We've already decided they're due, so it needn't.
+    fn background_args(&self, repositories: &[&str]) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec!["--background-child".into(), "--force".into()];
+
+        let key = self.key.to_possible_value().expect("no skipped variants");
+        args.extend(["--key".into(), key.get_name().into()]);
+        args.extend(["--jobs".into(), self.jobs.to_string().into()]);
+        args.extend([
+            "--jobs-per-host".into(),
+            self.jobs_per_host.to_string().into(),
+        ]);
+
+        if let Some(strategy) = self.strategy {
+            let strategy = strategy.to_possible_value().expect("no skipped variants");
+            args.extend(["--strategy".into(), strategy.get_name().into()]);
+        }
+        if self.autostash {
+            args.push("--autostash".into());
+        }
+        if let Some(retries) = self.retries {
+            args.extend(["--retries".into(), retries.to_string().into()]);
+        }
+        if let Some(timeout) = self.timeout {
+            args.extend(["--timeout".into(), timeout.to_string().into()]);
+        }
+        if self.allow_reset {
+            args.push("--allow-reset".into());
+        }
+        if let Some(config) = &self.config {
+            args.extend([
+                "--config".into(),
+                fs::canonicalize(config).unwrap_or(config.clone()).into(),
+            ]);
+        }
+
+        // The child is free to wander off to wherever it likes, so we'll give
+        // it absolute paths.
+        for repository in repositories {
+            args.push(fs::canonicalize(repository).map_or_else(|_| repository.into(), Into::into));
+        }
+
+        args
+    }
+
+    /// Whether git may prompt. Nobody's around to answer when we're run from
+    /// a script, a login hook, or in the background, and the first two of
+    /// those are a good deal more common than a human at the keyboard.
+    fn non_interactive(&self) -> bool {
+        if self.interactive {
+            false
+        } else {
+            self.non_interactive || !io::stdin().is_terminal()
+        }
+    }
+
+    /// Older versions took the max age as a second positional argument, as in
+    /// `ensure-update ~/some-repo 8`. That still works, so long as there's no
+    /// directory named "8" lying around.
+    ///
+    /// Something that was plainly meant as a max age, but isn't a valid one
+    /// (zero, say), is an error rather than a second repository.
+    fn apply_legacy_max_age(&mut self) -> Result<(), String> {
+        if self.max_age.is_some() || self.repositories.len() != 2 {
+            return Ok(());
+        }
+
+        let candidate = &self.repositories[1];
+        if Path::new(candidate).is_dir() {
+            return Ok(());
+        }
+
+        match candidate.parse() {
+            Ok(max_age) => {
+                self.max_age = Some(max_age);
+                self.repositories.pop();
+                Ok(())
+            }
+            Err(e) if looks_like_duration(candidate) => Err(e),
+            Err(_) => Ok(()),
+        }
+    }
+
+    /// Expands any `-` among the repositories into the paths listed on stdin,
+    /// one per line. With no repositories at all, we fall back to the ones
+    /// listed in the config file.
+    fn read_repositories(&self, config: &Config) -> io::Result<Vec<String>> {
+        if self.repositories.is_empty() {
+            let repositories: Vec<_> = config
+                .repositories()
+                .iter()
+                .map(|repository| repository.path().to_owned())
+                .collect();
+
+            if repositories.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "no repositories given, and none are configured",
+                ));
+            }
+
+            return Ok(repositories);
+        }
+
+        let mut repositories = Vec::with_capacity(self.repositories.len());
+        for repository in &self.repositories {
+            if repository != "-" {
+                repositories.push(repository.clone());
+                continue;
+            }
+
+            for line in io::stdin().lock().lines() {
+                let line = line?;
+                let line = line.trim();
+                if !line.is_empty() {
+                    repositories.push(line.into());
+                }
+            }
+        }
+        Ok(repositories)
+    }
+}
+
+/// Whether an argument reads as a duration, valid or not: a number, possibly
+/// negative, or an ISO 8601 duration like `PT4H`.
+fn looks_like_duration(s: &str) -> bool {
+    let s = s.trim();
+    let s = s.strip_prefix(['-', '+']).unwrap_or(s);
+    let s = s
+        .strip_prefix(['P', 'p'])
+        .map_or(s, |rest| rest.strip_prefix(['T', 't']).unwrap_or(rest));
+    s.starts_with(|c: char| c.is_ascii_digit())
+}
+
+enum Outcome {
+    Fresh,
+    Updated {
+        old_head: Option<String>,
+        new_head: Option<String>,
+        attempts: u32,
+        incoming: Option<Incoming>,
+        hooks: Vec<hook::Ran>,
+    },
+    /// The remote had nothing we didn't already have, so there was nothing
+    /// to update.
+    Checked {
+        head: Option<String>,
+    },
+    Skipped(Blocker),
+    /// A pre_update hook called the update off. The last hook is the one
+    /// that did it.
+    Vetoed(Vec<hook::Ran>),
+    Started,
+    Running,
+    Locked(u32),
+    BackingOff {
+        until: Timestamp,
+        streak: String,
+    },
 }
 
 fn main() {
-    if let Err(e) = run(Opts::parse()) {
+    let mut opts = Opts::parse();
+    if let Err(e) = opts.apply_legacy_max_age() {
+        Opts::command()
+            .error(clap::error::ErrorKind::ValueValidation, e)
+            .exit();
+    }
+
+    let exec = mem::take(&mut opts.exec);
+    let always_run = opts.always_run;
+    let detailed = opts.detailed_exit_codes;
+    let dry_run = opts.dry_run && opts.action.is_none();
+    let json = opts.output == Format::Json && opts.action.is_none();
+
+    let result = match &opts.action {
+        Some(Action::Config {
+            action: ConfigAction::Check,
+        }) => check_config(&opts)
+            .map(Exit::from_success)
+            .map_err(Error::from),
+        Some(Action::Check {
+            repository,
+            max_age,
+            remote,
+            key,
+            quiet,
+        }) => Ok(show_check(
+            &opts, repository, *max_age, *remote, *key, *quiet,
+        )),
+        Some(Action::Status { json, max_age }) => {
+            show_status(&opts, *json, *max_age).map(Exit::from_success)
+        }
+        None => run(opts),
+    };
+
+    let exit = result.unwrap_or_else(|e| {
         eprintln!("{e}");
-        process::exit(1);
+        let exit = Exit::from_error(&e);
+
+        // Whoever asked for JSON is expecting some, even if all it says is
+        // that we didn't get anywhere.
+        if json {
+            let report = Report {
+                exit_code: exit.code(detailed),
+                repositories: Vec::new(),
+                error: Some(e.to_string()),
+                error_kind: Some(e.kind()),
+            };
+            match serde_json::to_string_pretty(&report) {
+                Ok(json) => println!("{json}"),
+                Err(e) => eprintln!("{e}"),
+            }
+        }
+        exit
+    });
+
+    if dry_run && !exec.is_empty() {
+        println!("then: would run {}", describe(exec.iter().map(OsStr::new)));
+        process::exit(exit.code(detailed));
     }
+
+    // In wrapper mode, the update is just a prelude: once it's done, the
+    // command takes over this process entirely, exit code and all. Unless,
+    // that is, somebody hit Ctrl-C, in which case they want out.
+    if !exec.is_empty() && exit != Exit::Interrupted && (exit.success() || always_run) {
+        let e = exec_command(&exec);
+        eprintln!("{}: {e}", exec[0]);
+        process::exit(127);
+    }
+
+    process::exit(exit.code(detailed));
 }
 
-fn run(opts: Opts) -> io::Result<()> {
+/// Updates each repository that's due and says how it went.
+fn run(opts: Opts) -> Result<Exit, Error> {
     // First thing first, we need to check the last runtime of the command for
-    // this repository. If the last runtime was within the last n hours, we do
+    // each repository. If the last runtime was within the last n hours, we do
     // NOT need to run. Step one of this process is to grab our runtime table.
-    // This table (a hashmap) stores a list of repository names and the last
-    // time each one was updated by us.
+    // This table (a hashmap) stores a list of repository keys and the last
+    // time each one was updated by us. By default, the key is the canonical
+    // path of the repository.
+    //
+    // The table is loaded once and stored once, no matter how many
+    // repositories we've been handed. Other copies of this program may be
+    // doing the same thing at the same time, so storing it means applying
+    // our changes to the latest version, not just writing out our own.
+
+    let config = Config::load(opts.config.as_deref())?;
+    let repositories = opts.read_repositories(&config)?;
+    let settings: Vec<_> = repositories
+        .iter()
+        .map(|repository| opts.settings().or(config.settings(repository)))
+        .collect();
+    let hooks: Vec<_> = repositories
+        .iter()
+        .map(|repository| config.hooks(repository))
+        .collect();
 
-    let repository_name = get_name(&opts.repository)?;
-    let provider = Provider::builder("ensure-update")
-        .with_organization("hack-commons")
-        .build();
+    let state = State::new();
+
+    // If we're working in the background on someone else's behalf, they get
+    // to finish with the table before we start.
+    if opts.background_child {
+        background::wait_for_parent()?;
+    }
 
     // We need this to be mutable because we'll be updating it later.
-    let mut table: HashMap<String, Timestamp> = provider.load()?.into_inner();
-
-    // If the timestamp associated with our intended repository is newer than
-    // opts.max_age, we'll just return. Otherwise, if the timestamp in question
-    // is older than opts.max_age OR if there is no such timestamp, we'll
-    // continue with the update operation AND AFTER add/update a timestamp for
-    // this repository.
-    if is_recent(&table, &repository_name, opts.max_age) && !opts.force {
-        return Ok(());
+    let mut table = state.load()?;
+
+    // A dry run goes through the same motions, but only to say what it sees.
+    if opts.dry_run {
+        return Ok(explain(&opts, &repositories, &settings, &hooks, table));
     }
+    let mut changes = Changes::default();
+    let now = Timestamp::now();
+
+    // Each repository ends up either decided (fresh, or broken in some way)
+    // or due, in which case it goes into the queue for an update.
+    let mut results: Vec<Option<Result<Outcome, Error>>> = Vec::with_capacity(repositories.len());
+    let mut durations = vec![None; repositories.len()];
+    let mut due = Vec::new();
+
+    for (idx, repository) in repositories.iter().enumerate() {
+        let key = match key::resolve(repository, opts.key) {
+            Ok(key) => key,
+            Err(e) => {
+                results.push(Some(Err(e)));
+                continue;
+            }
+        };
+
+        // Tables written by older versions are keyed by directory name; if
+        // that's what we've got, carry the old timestamp over to the new key.
+        // This needs to be stored even if we don't update anything.
+        if let Some(legacy) = key::migrate(&mut table, repository, &key) {
+            changes.remove(&legacy);
+            changes.touch(&key);
+        }
 
-    // Before beginning the update process, we'll set our current directory
-    // to that of the target repository.
-    std::env::set_current_dir(&opts.repository)?;
+        // If a background update is (or was) working on this repository, now
+        // is the time to find out how it went.
+        if !opts.background_child
+            && let Some(record) = table.get_mut(&key)
+        {
+            if let Some(pid) = record.running() {
+                if lock::alive(pid) {
+                    results.push(Some(Ok(Outcome::Running)));
+                    continue;
+                }
 
-    // Next up, we'll prepare our git command.
-    let mut update_command = build_update_command();
+                record.attempted(
+                    now,
+                    AttemptResult::Failed {
+                        error: String::from("background update exited unexpectedly"),
+                    },
+                );
+                record.mark_unreported();
+                changes.touch(&key);
+            }
 
-    // In the event that we've passed the --verbose flag, we'll want to print
-    // the output of our command to stdout. Otherwise, we'll do this silently.
-    // Either way, we won't redirect stderr -- just in case.
-    if !opts.verbose {
-        update_command.stdout(Stdio::null());
+            if let Some(attempt) = record.take_report() {
+                let at = attempt.at.to_zoned(TimeZone::system()).strftime("%F %T");
+                eprintln!(
+                    "{repository}: background update at {at}: {}",
+                    attempt.result
+                );
+                changes.touch(&key);
+            }
+        }
+
+        // If the timestamp associated with our intended repository is newer
+        // than opts.max_age, we're done with it. Otherwise, if the timestamp
+        // in question is older than opts.max_age OR if there is no such
+        // timestamp, we'll continue with the update operation AND AFTER
+        // add/update a timestamp for this repository.
+        //
+        // A repository that keeps failing is due, but gets a break anyway,
+        // for longer after every failure; --force overrides this too.
+        let retry_after = table.get(&key).and_then(Record::retry_after);
+        if is_recent(&table, &key, settings[idx].max_age()) && !opts.force {
+            results.push(Some(Ok(Outcome::Fresh)));
+        } else if let Some(until) = retry_after.filter(|until| *until > now && !opts.force) {
+            let streak = table[&key].failure_streak();
+            results.push(Some(Ok(Outcome::BackingOff { until, streak })));
+        } else {
+            results.push(None);
+            due.push((idx, key));
+        }
     }
 
-    let result = update_command.status()?;
-    if !result.success() {
-        return Err(io::Error::other("repository failed to update"));
+    // In background mode, we hand the due repositories off to a detached
+    // process and get out of the way. The table records who's working on
+    // what, so that nobody else tries to do the same in the meantime.
+    if opts.background && !due.is_empty() {
+        let paths: Vec<_> = due
+            .iter()
+            .map(|(idx, _)| repositories[*idx].as_str())
+            .collect();
+        let mut child = background::spawn(opts.background_args(&paths))?;
+
+        for (idx, key) in due {
+            let pid = child.id();
+            table
+                .entry(key.clone())
+                .or_default()
+                .attempted(now, AttemptResult::Running { pid });
+            changes.touch(&key);
+            results[idx] = Some(Ok(Outcome::Started));
+        }
+
+        // Only once the table is stored can the child be allowed to go.
+        let stored = state.commit(&table, &changes);
+        drop(child.stdin.take());
+        stored?;
+
+        return Ok(summarize(&opts, &repositories, results, &durations));
     }
 
-    // Lastly, we're going to need to update our table with the timestamp of
-    // the update we just performed, and we'll store that table using the
-    // provider we already created.
+    // From here on, Ctrl-C stops whatever update is running and cleans up
+    // after it, and the table is still stored on the way out.
+    interrupt::install();
+
+    // Updates are network-bound, so with --jobs we'll run several at once,
+    // taking care not to pile too many onto any one server.
+    let capture = opts.jobs > 1 && due.len() > 1;
+    let due: Vec<_> = due
+        .into_iter()
+        .map(|(idx, key)| {
+            let host = pool::host(&repositories[idx], settings[idx].remote.as_deref());
+            (host, (idx, key))
+        })
+        .collect();
+
+    let updates = pool::execute(due, opts.jobs, opts.jobs_per_host, |(idx, key)| {
+        let start = Instant::now();
+        let mut lock = None;
+        let result = update(
+            &opts,
+            &state,
+            &repositories[*idx],
+            key,
+            &settings[*idx],
+            &hooks[*idx],
+            capture,
+            &mut lock,
+        );
+        (*idx, key.clone(), result, start.elapsed(), lock)
+    });
+
+    // Next, we're going to need to update our table with the timestamp of
+    // each update we just performed. Repositories we skipped or failed to
+    // update get a note to that effect, but remain due.
+    let now = Timestamp::now();
+    let mut called_off = Vec::new();
+    let mut locks = Vec::new();
+    for (idx, key, result, duration, lock) in updates {
+        durations[idx] = Some(duration);
+        locks.extend(lock);
 
-    table.insert(repository_name, Timestamp::now());
-    provider.store(table)?;
+        // Somebody else got to these first, or we were stopped before we got
+        // anywhere. Either way, there's nothing to record.
+        //
+        // A vetoed update is as good as never tried: a hook that says "not
+        // now" shouldn't cost the repository its place in the queue.
+        //
+        // In the background, though, our parent left a note saying we're on
+        // it, and if we leave that lying around, the next run will take it
+        // for a crash.
+        if let Ok(Outcome::Fresh | Outcome::Locked(_) | Outcome::Vetoed(_))
+        | Err(Error::Interrupted) = result
+        {
+            if opts.background_child {
+                match &result {
+                    Ok(Outcome::Locked(pid)) => {
+                        called_off.push((key, format!("already being updated by pid {pid}")))
+                    }
+                    Ok(Outcome::Vetoed(hooks)) => {
+                        let veto = hooks.last().expect("somebody vetoed");
+                        called_off.push((key, format!("vetoed: {veto}")));
+                    }
+                    Err(Error::Interrupted) => called_off.push((key, String::from("interrupted"))),
+                    _ => {}
+                }
+            }
+            results[idx] = Some(result);
+            continue;
+        }
 
-    Ok(())
+        let record = table.entry(key.clone()).or_default();
+        record.locate(&repositories[idx], &settings[idx]);
+        match &result {
+            Ok(Outcome::Updated {
+                old_head, new_head, ..
+            }) => record.updated(
+                now,
+                Update {
+                    head_before: old_head.clone(),
+                    head_after: new_head.clone(),
+                    duration: SignedDuration::try_from(duration).unwrap_or(SignedDuration::MAX),
+                },
+            ),
+            Ok(Outcome::Checked { head }) => record.updated(
+                now,
+                Update {
+                    head_before: head.clone(),
+                    head_after: head.clone(),
+                    duration: SignedDuration::try_from(duration).unwrap_or(SignedDuration::MAX),
+                },
+            ),
+            Ok(Outcome::Skipped(blocker)) => record.attempted(
+                now,
+                AttemptResult::Skipped {
+                    reason: blocker.to_string(),
+                },
+            ),
+            Ok(_) => unreachable!("an update is either done, checked, or skipped"),
+            Err(Error::TimedOut(timeout)) => record.attempted(
+                now,
+                AttemptResult::TimedOut {
+                    after: SignedDuration::try_from(*timeout).unwrap_or(SignedDuration::MAX),
+                },
+            ),
+            Err(e) => record.attempted(
+                now,
+                AttemptResult::Failed {
+                    error: e.to_string(),
+                },
+            ),
+        }
+
+        // Nobody is around to see how a background update went, so we'll
+        // leave a note for the next one to come along.
+        if opts.background_child {
+            record.mark_unreported();
+        }
+
+        changes.touch(&key);
+        results[idx] = Some(result);
+    }
+
+    // Lastly, if anything changed, we'll store the table.
+    state.commit(&table, &changes)?;
+
+    // Whoever had the lock may have recorded an update since we loaded the
+    // table, so rather than store our stale copy, we only take back the note
+    // that says we're running, if it's still there.
+    if !called_off.is_empty() {
+        state.modify(|latest| {
+            for (key, reason) in called_off {
+                if let Some(record) = latest.get_mut(&key)
+                    && record.running() == Some(process::id())
+                {
+                    record.attempted(now, AttemptResult::Skipped { reason });
+                    record.mark_unreported();
+                }
+            }
+        })?;
+    }
+
+    // Only now that everyone can see how our updates went do we let go of
+    // the repositories. Anybody waiting on one of them will find it fresh,
+    // rather than update it all over again.
+    drop(locks);
+
+    Ok(summarize(&opts, &repositories, results, &durations))
 }
 
-fn is_recent(table: &HashMap<String, Timestamp>, repository_name: &str, max_age: i32) -> bool {
-    let Some(&timestamp) = table.get(repository_name) else {
+/// Says what `run` would do with each repository, and why, without running
+/// git or storing anything.
+fn explain(
+    opts: &Opts,
+    repositories: &[String],
+    settings: &[Settings],
+    hooks: &[Hooks],
+    mut table: Table,
+) -> Exit {
+    let now = Timestamp::now();
+    let mut exit = Exit::Fresh;
+
+    for ((repository, settings), hooks) in repositories.iter().zip(settings).zip(hooks) {
+        println!("{repository}:");
+
+        let key = match key::resolve(repository, opts.key) {
+            Ok(key) => key,
+            Err(e) => {
+                println!("  error: {e}");
+                exit = exit.max(Exit::from_error(&e));
+                continue;
+            }
+        };
+        println!("  key: {key}");
+
+        // Migrating a legacy key happens in memory here, same as it would
+        // for real; it just never makes it to disk.
+        let migrated = key::migrate(&mut table, repository, &key);
+        if let Some(legacy) = &migrated {
+            println!("  legacy key: {legacy} (would be moved over)");
+        }
+
+        let record = table.get(&key);
+        let max_age = settings.max_age();
+        match record.and_then(|record| record.updated) {
+            Some(updated) => {
+                let at = updated.to_zoned(TimeZone::system()).strftime("%F %T");
+                let elapsed = now.duration_since(updated);
+                println!("  last updated: {at} ({} ago)", age::friendly(elapsed));
+                if elapsed < max_age.duration() {
+                    let left = age::friendly(max_age.duration() - elapsed);
+                    println!("  max age: {max_age} (due in {left})");
+                } else {
+                    let over = age::friendly(elapsed - max_age.duration());
+                    println!("  max age: {max_age} (overdue by {over})");
+                }
+            }
+            None => println!("  last updated: never (max age: {max_age})"),
+        }
+
+        let running = record
+            .filter(|_| !opts.background_child)
+            .and_then(Record::running)
+            .filter(|pid| lock::alive(*pid));
+        let retry_after = record
+            .and_then(Record::retry_after)
+            .filter(|until| *until > now);
+
+        let due = if is_recent(&table, &key, max_age) && !opts.force {
+            println!("  decision: fresh; nothing to do");
+            false
+        } else if let Some(pid) = running {
+            println!("  decision: already updating in the background (pid {pid})");
+            false
+        } else if let Some(until) = retry_after.filter(|_| !opts.force) {
+            let wait = age::friendly(until.duration_since(now));
+            let streak = table[&key].failure_streak();
+            println!("  decision: backing off ({streak}; next try in {wait})");
+            false
+        } else {
+            let forced = if opts.force { " (forced)" } else { "" };
+            println!("  decision: due{forced}");
+            true
+        };
+
+        if due && settings.strategy() != Strategy::ResetToUpstream {
+            println!(
+                "  first: would ask the remote whether there's anything new, and stop there if not"
+            );
+        }
+
+        if due {
+            // Hooks aren't run, of course, so all we can say is when they
+            // would be.
+            for hook in &hooks.pre_update {
+                println!(
+                    "  pre_update hook: `{}` would run first; if it fails, the update is called off",
+                    hook.run
+                );
+            }
+
+            // Whether the working tree is fit to update is only found out by
+            // asking git, so the commands are the ones for a clean one.
+            let directory = fs::canonicalize(repository)
+                .map_or_else(|_| repository.clone(), |path| path.display().to_string());
+            match build_update_commands(settings, settings.autostash()) {
+                Ok(commands) => {
+                    for command in commands {
+                        let program = iter::once(command.get_program()).chain(command.get_args());
+                        println!("  would run: {} (in {directory})", describe(program));
+                    }
+                    for hook in &hooks.post_update {
+                        if hook.paths.is_empty() {
+                            println!(
+                                "  post_update hook: `{}` would run if new commits come in",
+                                hook.run
+                            );
+                        } else {
+                            println!(
+                                "  post_update hook: `{}` would run if new commits change {}",
+                                hook.run,
+                                hook.paths.join(", ")
+                            );
+                        }
+                    }
+                    if opts.background {
+                        println!("  (in a detached background process)");
+                    }
+                }
+                Err(e) => {
+                    println!("  error: {e}");
+                    exit = exit.max(Exit::from_error(&e.into()));
+                }
+            }
+        }
+
+        if due && !hooks.pre_update.is_empty() && migrated.is_none() {
+            println!("  table: would be updated, unless a pre_update hook calls it off");
+        } else if due || migrated.is_some() {
+            println!("  table: would be updated");
+        } else {
+            println!("  table: would be left alone");
+        }
+    }
+
+    exit
+}
+
+/// Renders a command line the way you'd type it into a shell.
+fn describe<'a>(words: impl Iterator<Item = &'a OsStr>) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c);
+    words
+        .map(|word| {
+            let word = word.to_string_lossy();
+            if !word.is_empty() && word.chars().all(safe) {
+                word.into_owned()
+            } else {
+                format!("'{}'", word.replace('\'', "'\\''"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Prints what happened to each repository and works out the exit code.
+fn summarize(
+    opts: &Opts,
+    repositories: &[String],
+    results: Vec<Option<Result<Outcome, Error>>>,
+    durations: &[Option<Duration>],
+) -> Exit {
+    // A lone repository gets the same quiet treatment it always has. With
+    // more than one, you probably want to know which did what.
+    let json = opts.output == Format::Json;
+    let summarize = !json && (repositories.len() > 1 || opts.verbose);
+    let results: Vec<_> = results
+        .into_iter()
+        .map(|result| result.expect("every repository is decided"))
+        .collect();
+
+    let mut exit = Exit::Fresh;
+    let mut entries = Vec::with_capacity(repositories.len());
+
+    for ((repository, result), duration) in repositories.iter().zip(&results).zip(durations) {
+        let mut entry = Entry::new(repository, "");
+        entry.duration = *duration;
+
+        let status = match result {
+            Ok(Outcome::Fresh) => {
+                entry.decision = "fresh";
+                if summarize {
+                    println!("{repository}: fresh");
+                }
+                Exit::Fresh
+            }
+            Ok(Outcome::Updated {
+                old_head,
+                new_head,
+                attempts,
+                incoming,
+                hooks,
+            }) => {
+                let changed = old_head != new_head;
+                let failed: Vec<_> = hooks.iter().filter(|hook| !hook.success).collect();
+                entry.decision = "updated";
+                entry.old_head = old_head.as_deref();
+                entry.new_head = new_head.as_deref();
+                entry.attempts = Some(*attempts);
+                entry.changes = incoming.as_ref();
+                entry.hooks = hooks;
+
+                // Having asked what came in, you'll want to hear it, even
+                // about a lone repository.
+                if summarize || (incoming.is_some() && !json) {
+                    let mut notes = Vec::new();
+                    if !changed {
+                        notes.push(String::from("no changes"));
+                    }
+                    if *attempts > 1 {
+                        notes.push(format!("after {attempts} attempts"));
+                    }
+                    if notes.is_empty() {
+                        println!("{repository}: updated");
+                    } else {
+                        println!("{repository}: updated ({})", notes.join(", "));
+                    }
+                    if let Some(incoming) = incoming {
+                        println!("{incoming}");
+                    }
+                }
+
+                // Hooks can be picky about what they run for, so we'll say
+                // which ones ran, and what set them off.
+                for hook in hooks {
+                    let triggered = format!(
+                        "{} hook `{}` triggered ({})",
+                        hook.stage, hook.command, hook.reason
+                    );
+                    if summarize {
+                        println!("{repository}: {triggered}");
+                    } else if !json {
+                        println!("{triggered}");
+                    }
+                }
+
+                // The update itself went fine, and is recorded as such; a
+                // hook that didn't is a problem of its own.
+                for hook in &failed {
+                    if summarize {
+                        eprintln!("{repository}: {hook}");
+                    } else if !json {
+                        eprintln!("{hook}");
+                    }
+                }
+                if !failed.is_empty() {
+                    Exit::HookFailed
+                } else if changed {
+                    Exit::Changed
+                } else {
+                    Exit::Unchanged
+                }
+            }
+            Ok(Outcome::Checked { head }) => {
+                entry.decision = "checked";
+                entry.old_head = head.as_deref();
+                entry.new_head = head.as_deref();
+                if summarize {
+                    println!("{repository}: checked, no changes");
+                }
+                Exit::Unchanged
+            }
+            Ok(Outcome::Skipped(blocker)) => {
+                entry.decision = "skipped";
+                entry.reason = Some(blocker.to_string());
+                if summarize {
+                    println!("{repository}: skipped ({blocker})");
+                } else if !json {
+                    eprintln!("skipped: {blocker}");
+                }
+                Exit::Skipped
+            }
+            Ok(Outcome::Started) => {
+                entry.decision = "started";
+                if summarize {
+                    println!("{repository}: updating in the background");
+                }
+                Exit::Deferred
+            }
+            Ok(Outcome::Running) => {
+                entry.decision = "running";
+                if summarize {
+                    println!("{repository}: already updating in the background");
+                }
+                Exit::Deferred
+            }
+            Ok(Outcome::Vetoed(hooks)) => {
+                let veto = hooks.last().expect("somebody vetoed");
+                entry.decision = "vetoed";
+                entry.reason = Some(veto.to_string());
+                entry.hooks = hooks;
+                if summarize {
+                    println!("{repository}: vetoed by hook ({veto})");
+                } else if !json {
+                    eprintln!("vetoed by hook ({veto})");
+                }
+                Exit::Vetoed
+            }
+            Ok(Outcome::Locked(pid)) => {
+                entry.decision = "locked";
+                entry.reason = Some(format!("already being updated (pid {pid})"));
+                if summarize {
+                    println!("{repository}: already being updated (pid {pid})");
+                }
+                Exit::Deferred
+            }
+            Ok(Outcome::BackingOff { until, streak }) => {
+                let wait = age::friendly(until.duration_since(Timestamp::now()));
+                let reason = format!("{streak}; next try in {wait}");
+                entry.decision = "backing-off";
+                if summarize {
+                    println!("{repository}: backing off ({reason})");
+                }
+                entry.reason = Some(reason);
+                Exit::BackingOff
+            }
+            Err(e) => {
+                entry.decision = match e {
+                    Error::Interrupted => "interrupted",
+                    _ => "failed",
+                };
+                entry.error = Some(e.to_string());
+                entry.error_kind = Some(e.kind());
+                if let Error::GaveUp { attempts, .. } = e {
+                    entry.attempts = Some(*attempts);
+                }
+                if summarize {
+                    eprintln!("{repository}: failed: {e}");
+                } else if !json {
+                    eprintln!("{e}");
+                }
+                Exit::from_error(e)
+            }
+        };
+
+        exit = exit.max(status);
+        entries.push(entry);
+    }
+
+    if json {
+        let report = Report {
+            exit_code: exit.code(opts.detailed_exit_codes),
+            repositories: entries,
+            error: None,
+            error_kind: None,
+        };
+        match serde_json::to_string_pretty(&report) {
+            Ok(json) => println!("{json}"),
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+
+    exit
+}
+
+/// Serializes grouped output from concurrent updates.
+static OUTPUT: Mutex<()> = Mutex::new(());
+
+/// Updates one repository. The repository's lock is left in `lock`, to be
+/// held until how the update went has been stored.
+#[allow(clippy::too_many_arguments)]
+fn update(
+    opts: &Opts,
+    state: &State,
+    repository: &str,
+    key: &str,
+    settings: &Settings,
+    hooks: &Hooks,
+    capture: bool,
+    lock: &mut Option<Lock>,
+) -> Result<Outcome, Error> {
+    // If we've been told to stop, we won't start anything new.
+    if interrupt::requested() {
+        return Err(Error::Interrupted);
+    }
+
+    // Only one update per repository at a time. If somebody else is already
+    // on it, we'll either leave them to it or wait our turn.
+    let lock_path = Lock::repository(key)?;
+    *lock = match Lock::try_acquire(&lock_path)? {
+        Ok(lock) => Some(lock),
+        Err(pid) if !opts.wait_for_lock => return Ok(Outcome::Locked(pid)),
+        Err(_) => {
+            let lock = Lock::acquire(&lock_path, None)?;
+
+            // Whoever we were waiting on may well have just done our job for
+            // us, in which case we can call it a day.
+            if !opts.force && is_recent(&state.load()?, key, settings.max_age()) {
+                return Ok(Outcome::Fresh);
+            }
+            Some(lock)
+        }
+    };
+
+    // Before anything else, make sure the working tree is in a state we can
+    // update without making a mess. A fetch can't make a mess, and a hard
+    // reset is *supposed* to throw away uncommitted changes, so those get a
+    // lighter check.
+    let strategy = settings.strategy();
+    let mut autostash = false;
+
+    if strategy != Strategy::FetchOnly {
+        let check_changes = strategy != Strategy::ResetToUpstream;
+        if let Some(blocker) = worktree::check(repository, check_changes)? {
+            if !(settings.autostash() && blocker.autostashable()) {
+                return Ok(Outcome::Skipped(blocker));
+            }
+            autostash = true;
+        }
+    }
+
+    // Asking the remote what it's got is a good deal cheaper than fetching
+    // it, and if we've got all of it already, there's nothing to merge
+    // either. A hard reset is about the working tree as much as upstream, so
+    // it always goes ahead.
+    if strategy != Strategy::ResetToUpstream {
+        match up_to_date(repository, settings, opts.non_interactive()) {
+            Ok(true) => {
+                return Ok(Outcome::Checked {
+                    head: git::head(Path::new(repository)),
+                });
+            }
+            // A remote that's too slow to answer this will be too slow to
+            // fetch from, and there's no sense waiting on it twice.
+            Err(error @ (Error::Interrupted | Error::TimedOut(_))) => return Err(error),
+
+            // Whatever else went wrong, the update proper will run into it
+            // too, and it knows how to retry and report it.
+            Ok(false) | Err(_) => {}
+        }
+    }
+
+    // Next, we'll prepare our git commands, which will run in the target
+    // repository. Most strategies need just the one.
+    let commands = build_update_commands(settings, autostash)?;
+    let old_head = git::head(Path::new(repository));
+
+    // When other updates are running alongside this one, we hang on to the
+    // output and print it in one piece at the end, so that it doesn't end up
+    // interleaved with everyone else's.
+    let json = opts.output == Format::Json;
+    let mut captured = (capture || json).then(|| (Vec::new(), Vec::new()));
+    let mut failure = None;
+    let mut attempts = 1;
+
+    // Hooks get to know where they are and what's going on.
+    let path = Path::new(repository);
+    let canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let canonical_str = canonical.to_string_lossy();
+
+    // Last chance to call the update off. Whatever a pre_update hook has to
+    // say (a build in progress, a server that's running), it says it by
+    // failing.
+    let mut ran = Vec::new();
+    let env = [
+        ("ENSURE_UPDATE_REPOSITORY", &*canonical_str),
+        ("ENSURE_UPDATE_OLD_HEAD", old_head.as_deref().unwrap_or("")),
+    ];
+    for hook in &hooks.pre_update {
+        let output = hook::Output::new(captured.as_mut(), settings.verbose(), json);
+        let hook = hook.run(
+            "pre_update",
+            String::from("before updating"),
+            &canonical,
+            &env,
+            opts.non_interactive(),
+            output,
+        );
+        let vetoed = !hook.success;
+        ran.push(hook);
+
+        if vetoed {
+            if let Some((stdout, stderr)) = captured {
+                print_grouped(repository, settings.verbose(), json, &stdout, &stderr)?;
+            }
+            // A hook killed by Ctrl-C didn't so much veto as get caught up
+            // in it.
+            if interrupt::requested() {
+                return Err(Error::Interrupted);
+            }
+            return Ok(Outcome::Vetoed(ran));
+        }
+    }
+
+    'commands: for mut update_command in commands {
+        update_command.current_dir(repository);
+        if opts.non_interactive() {
+            git::non_interactive(&mut update_command, Path::new(repository));
+        }
+
+        // Either way, we keep a copy of stderr, because that's where git
+        // tells us what went wrong.
+        //
+        // In the event that we've passed the --verbose flag, we'll want to
+        // print the output of our command to stdout. Otherwise, we'll do
+        // this silently. Either way, we won't redirect stderr -- just in
+        // case.
+        if captured.is_some() {
+            update_command.stdout(Stdio::piped());
+        } else if !settings.verbose() {
+            update_command.stdout(Stdio::null());
+        }
+
+        loop {
+            // A command that hangs, or that we're told to abandon, is killed,
+            // and whatever it left half done is cleaned up like any other
+            // failure.
+            let finished = if interrupt::requested() {
+                Err(Error::Interrupted)
+            } else {
+                git::run(
+                    &mut update_command,
+                    captured.is_none(),
+                    settings.timeout(),
+                    opts.non_interactive(),
+                )
+            };
+
+            let finished = match finished {
+                Ok(finished) => finished,
+                Err(error @ (Error::TimedOut(_) | Error::Interrupted)) => {
+                    failure = Some(Some(error));
+                    break 'commands;
+                }
+                Err(error) => return Err(error),
+            };
+
+            if let Some((stdout, stderr)) = &mut captured {
+                stdout.extend(&finished.stdout);
+                stderr.extend(&finished.stderr);
+            }
+
+            if finished.status.success() {
+                break;
+            }
+
+            // A failure that might not last gets another go or two, after a
+            // pause. Anything else is final.
+            let error = Error::classify(&String::from_utf8_lossy(&finished.stderr));
+            if attempts > settings.retries() || !error.as_ref().is_some_and(Error::is_transient) {
+                failure = Some(error);
+                break 'commands;
+            }
+
+            let delay = retry::delay(attempts);
+            let error = error.expect("transient errors are classified");
+            eprintln!(
+                "{repository}: {error}; retrying in {:.1}s",
+                delay.as_secs_f64()
+            );
+            interrupt::sleep(delay);
+            attempts += 1;
+        }
+    }
+
+    let error = failure.map(|error| {
+        let aborted = strategy.recover(repository);
+        let error = match error {
+            Some(Error::Conflict { .. }) => Error::Conflict { aborted },
+            Some(error) => error,
+            None => Error::Git(strategy.failure().into()),
+        };
+
+        if attempts > 1 && !matches!(error, Error::Interrupted) {
+            Error::GaveUp {
+                attempts,
+                last: Box::new(error),
+            }
+        } else {
+            error
+        }
+    });
+
+    if let Some(error) = error {
+        if let Some((stdout, stderr)) = captured {
+            print_grouped(repository, settings.verbose(), json, &stdout, &stderr)?;
+        }
+        return Err(error);
+    }
+
+    // What came in is worth knowing, but not worth failing over.
+    let new_head = git::head(path);
+    let incoming = match (&old_head, &new_head) {
+        (Some(old), Some(new)) if old != new => Incoming::collect(path, old, new, opts.changes)
+            .unwrap_or_else(|e| {
+                eprintln!("{repository}: unable to work out what changed: {e}");
+                None
+            }),
+        _ => None,
+    };
+
+    // Hooks are for reacting to new commits (rebuilding, reinstalling), so
+    // there's no call for them when nothing came in.
+    let mut interrupted = false;
+    if let Some(new) = new_head
+        .as_deref()
+        .filter(|new| old_head.as_deref() != Some(new))
+    {
+        let env = [
+            ("ENSURE_UPDATE_REPOSITORY", &*canonical_str),
+            ("ENSURE_UPDATE_OLD_HEAD", old_head.as_deref().unwrap_or("")),
+            ("ENSURE_UPDATE_NEW_HEAD", new),
+        ];
+        for hook in &hooks.post_update {
+            // Ctrl-C stops the hooks along with everything else.
+            if interrupt::requested() {
+                interrupted = true;
+                break;
+            }
+
+            let Some(reason) = hook.trigger(&canonical, old_head.as_deref(), new) else {
+                continue;
+            };
+            let output = hook::Output::new(captured.as_mut(), settings.verbose(), json);
+            let hook = hook.run(
+                "post_update",
+                reason,
+                &canonical,
+                &env,
+                opts.non_interactive(),
+                output,
+            );
+
+            // As with pre_update hooks, one killed by Ctrl-C didn't fail so
+            // much as get caught up in it.
+            let killed = !hook.success && interrupt::requested();
+            ran.push(hook);
+            if killed {
+                interrupted = true;
+                break;
+            }
+        }
+    }
+
+    if let Some((stdout, stderr)) = captured {
+        print_grouped(repository, settings.verbose(), json, &stdout, &stderr)?;
+    }
+    if interrupted {
+        return Err(Error::Interrupted);
+    }
+
+    Ok(Outcome::Updated {
+        old_head,
+        new_head,
+        attempts,
+        incoming,
+        hooks: ran,
+    })
+}
+
+/// Prints captured output under a header naming the repository. With JSON
+/// output, stdout belongs to the report, so everything goes to stderr.
+fn print_grouped(
+    repository: &str,
+    verbose: bool,
+    json: bool,
+    stdout: &[u8],
+    stderr: &[u8],
+) -> io::Result<()> {
+    let show_stdout = verbose && !stdout.is_empty();
+    if !show_stdout && stderr.is_empty() {
+        return Ok(());
+    }
+
+    let _guard = OUTPUT.lock().unwrap();
+    let mut out: Box<dyn Write> = if json {
+        Box::new(io::stderr().lock())
+    } else {
+        Box::new(io::stdout().lock())
+    };
+    let mut err = io::stderr().lock();
+    writeln!(out, "==> {repository}")?;
+    if show_stdout {
+        out.write_all(stdout)?;
+    }
+    out.flush()?;
+    err.write_all(stderr)?;
+    err.flush()
+}
+
+fn is_recent(table: &Table, repository_key: &str, max_age: MaxAge) -> bool {
+    let Some(timestamp) = table.get(repository_key).and_then(|record| record.updated) else {
         return false;
     };
 
     // No idea why Spans can't be compared, but SignedDurations can, so I guess
     // that's what we're gonna use. /shrug
     let elapsed = timestamp.duration_until(Timestamp::now()).abs();
-    SignedDuration::from_hours(max_age as i64) > elapsed
+    max_age.duration() > elapsed
 }
 
-fn build_update_command() -> Command {
-    let mut command = Command::new("git");
-    command.arg("pull");
-    command
+/// Whether a repository already has everything its upstream has to offer:
+/// the remote hasn't moved since we last fetched, and (unless fetching is all
+/// we do) the local branch contains what we fetched.
+fn up_to_date(repository: &str, settings: &Settings, non_interactive: bool) -> Result<bool, Error> {
+    let path = Path::new(repository);
+    let upstream = Upstream::resolve(path, settings.remote.as_deref(), settings.branch.as_deref())?;
+    let Some(remote) = upstream.remote(path, non_interactive, settings.timeout())? else {
+        return Ok(false);
+    };
+    if upstream.local(path).as_ref() != Some(&remote) {
+        return Ok(false);
+    }
+
+    if settings.strategy() == Strategy::FetchOnly {
+        return Ok(true);
+    }
+    Ok(git::output(path, &["merge-base", "--is-ancestor", &remote, "HEAD"]).is_ok())
 }
 
-fn get_name(repository: &str) -> io::Result<String> {
-    let path = Path::new(repository);
+fn build_update_commands(settings: &Settings, autostash: bool) -> io::Result<Vec<Command>> {
+    settings.strategy().commands(
+        settings.remote.as_deref(),
+        settings.branch.as_deref(),
+        autostash,
+        settings.allow_reset(),
+    )
+}
+
+/// Replaces this process with the given command. Only returns if the command
+/// could not be started.
+#[cfg(unix)]
+fn exec_command(command: &[String]) -> io::Error {
+    use std::os::unix::process::CommandExt;
+    Command::new(&command[0]).args(&command[1..]).exec()
+}
 
-    // This is only legal for directories; a repository MUST
-    // be a folder.
-    if !path.is_dir() {
-        return Err(io::Error::new(io::ErrorKind::NotFound, "not a directory"));
+/// Runs the given command and exits with its exit code. Only returns if the
+/// command could not be started.
+#[cfg(not(unix))]
+fn exec_command(command: &[String]) -> io::Error {
+    match Command::new(&command[0]).args(&command[1..]).status() {
+        Ok(status) => process::exit(status.code().unwrap_or(1)),
+        Err(e) => e,
+    }
+}
+
+/// Handles its own errors, since --quiet means quiet.
+fn show_check(
+    opts: &Opts,
+    repository: &str,
+    max_age: Option<MaxAge>,
+    ask_remote: bool,
+    key: KeyMode,
+    quiet: bool,
+) -> Exit {
+    let verdict = Config::load(opts.config.as_deref())
+        .map_err(Error::from)
+        .and_then(|config| check::check(repository, key, &config, max_age, ask_remote));
+
+    match verdict {
+        Ok(verdict) => {
+            if !quiet {
+                println!("{verdict}");
+            }
+            Exit::from_success(!verdict.due())
+        }
+        Err(e) => {
+            if !quiet {
+                eprintln!("{e}");
+            }
+            Exit::from_error(&e)
+        }
+    }
+}
+
+fn show_status(opts: &Opts, json: bool, max_age: Option<MaxAge>) -> Result<bool, Error> {
+    let config = Config::load(opts.config.as_deref())?;
+    let table = State::new().load()?;
+    status::print(&table, &config, max_age, json)?;
+    Ok(true)
+}
+
+fn check_config(opts: &Opts) -> io::Result<bool> {
+    let path = match &opts.config {
+        Some(path) => path.clone(),
+        None => Config::default_path()
+            .ok_or_else(|| io::Error::other("unable to locate configuration directory"))?,
+    };
+
+    if !path.exists() {
+        println!("{}: no such file", path.display());
+        return Ok(false);
+    }
+
+    let (config, unknown) = Config::load_checked(Some(&path))?;
+    let issues = config.check(unknown);
+    if issues.is_empty() {
+        println!("{}: ok", path.display());
+        return Ok(true);
     }
 
-    // The only error case here is if the path provided looks like "/"
-    let name = path.file_name().ok_or_else(|| {
-        io::Error::new(io::ErrorKind::NotFound, "did you attempt to update root?")
-    })?;
+    for issue in issues {
+        match issue {
+            Issue::UnknownKey(key) => println!("{}: unknown key: {key}", path.display()),
+            Issue::BadPath {
+                path: repository,
+                reason,
+            } => {
+                println!("{}: {repository}: {reason}", path.display())
+            }
+        }
+    }
+
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // In theory, I'm not best pleased with this solution, because it means
-    // that unequal paths could be treated equally if they map to the same
-    // lossy string. However, I just don't care enough. None of my repositories
-    // have non-ASCII names anyway.
-    Ok(name.to_string_lossy().into())
+    fn legacy(args: &[&str]) -> (Result<(), String>, Opts) {
+        let mut opts = Opts::parse_from(args);
+        (opts.apply_legacy_max_age(), opts)
+    }
+
+    #[test]
+    fn legacy_max_age_is_taken_from_a_second_argument() {
+        let (result, opts) = legacy(&["ensure-update", "/nonexistent/repo", "8"]);
+        assert_eq!(result, Ok(()));
+        assert_eq!(opts.repositories, ["/nonexistent/repo"]);
+        assert_eq!(opts.max_age, Some("8".parse().unwrap()));
+    }
+
+    #[test]
+    fn invalid_legacy_max_age_is_an_error() {
+        for max_age in ["0", "0h", "PT0S"] {
+            let (result, _) = legacy(&["ensure-update", "/nonexistent/repo", max_age]);
+            assert!(
+                result.unwrap_err().contains("greater than zero"),
+                "{max_age}"
+            );
+        }
+    }
+
+    #[test]
+    fn other_second_arguments_are_repositories() {
+        let (result, opts) = legacy(&["ensure-update", "/nonexistent/a", "notes"]);
+        assert_eq!(result, Ok(()));
+        assert_eq!(opts.repositories.len(), 2);
+        assert_eq!(opts.max_age, None);
+    }
+
+    #[test]
+    fn durations_are_recognized_valid_or_not() {
+        for s in ["0", "8", "-3", "0h", "1d12h", "PT0S", "P1D"] {
+            assert!(looks_like_duration(s), "{s}");
+        }
+        for s in ["notes", "Projects", "./8", "h4"] {
+            assert!(!looks_like_duration(s), "{s}");
+        }
+    }
 }
@@ -0,0 +1,181 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Condvar, Mutex},
+    thread,
+};
+
+use crate::git;
+
+/// Runs `f` over `items` using up to `jobs` worker threads, with no more than
+/// `per_host` items sharing a remote host in flight at any one time.
+///
+/// Each item is paired with its host (see [`host`]). Results are returned in
+/// the same order as the items they came from.
+pub fn execute<T, R, F>(items: Vec<(String, T)>, jobs: usize, per_host: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    // Nothing to be gained from spinning up threads for this.
+    if jobs <= 1 || items.len() <= 1 {
+        return items.iter().map(|(_, item)| f(item)).collect();
+    }
+
+    let pool = Pool {
+        state: Mutex::new(State {
+            pending: (0..items.len()).collect(),
+            active: HashMap::new(),
+        }),
+        ready: Condvar::new(),
+        per_host: per_host.max(1),
+    };
+
+    let results: Mutex<Vec<Option<R>>> = Mutex::new((0..items.len()).map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.min(items.len()) {
+            scope.spawn(|| {
+                while let Some(idx) = pool.take(&items) {
+                    let (host, item) = &items[idx];
+                    let result = f(item);
+                    results.lock().unwrap()[idx] = Some(result);
+                    pool.release(host);
+                }
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|result| result.expect("every item is processed"))
+        .collect()
+}
+
+/// Works out which host a repository's remote (origin, by default) lives on.
+///
+/// Anything that isn't obviously a network remote (local paths, mostly, or a
+/// repository with no remote at all) is lumped in with everything else on
+/// the local machine.
+pub fn host(repository: &str, remote: Option<&str>) -> String {
+    let Ok(url) = git::remote_url(Path::new(repository), remote.unwrap_or("origin")) else {
+        return String::from("localhost");
+    };
+
+    parse_host(&url).unwrap_or("localhost").into()
+}
+
+fn parse_host(url: &str) -> Option<&str> {
+    // Proper urls look like scheme://[user@]host[:port]/path...
+    if let Some((_, rest)) = url.split_once("://") {
+        let authority = rest.split('/').next()?;
+        let host = authority.rsplit('@').next()?;
+        let host = host.split(':').next()?;
+        return (!host.is_empty()).then_some(host);
+    }
+
+    // ...while scp-style remotes look like [user@]host:path. A colon after a
+    // slash means this is actually a local path with a colon in it.
+    let (authority, _) = url.split_once(':')?;
+    if authority.contains('/') {
+        return None;
+    }
+    let host = authority.rsplit('@').next()?;
+    (!host.is_empty()).then_some(host)
+}
+
+struct Pool {
+    state: Mutex<State>,
+    ready: Condvar,
+    per_host: usize,
+}
+
+struct State {
+    pending: Vec<usize>,
+    active: HashMap<String, usize>,
+}
+
+impl Pool {
+    /// Claims the next item whose host has a free slot, waiting for one to
+    /// open up if need be. Returns `None` once everything has been claimed.
+    fn take<T>(&self, items: &[(String, T)]) -> Option<usize> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.pending.is_empty() {
+                return None;
+            }
+
+            let available = state.pending.iter().position(|&idx| {
+                let host = &items[idx].0;
+                state.active.get(host).copied().unwrap_or(0) < self.per_host
+            });
+
+            if let Some(position) = available {
+                let idx = state.pending.remove(position);
+                *state.active.entry(items[idx].0.clone()).or_default() += 1;
+                return Some(idx);
+            }
+
+            state = self.ready.wait(state).unwrap();
+        }
+    }
+
+    fn release(&self, host: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(count) = state.active.get_mut(host) {
+            *count -= 1;
+        }
+        self.ready.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_host_from_urls() {
+        assert_eq!(
+            parse_host("https://github.com/yt-dlp/yt-dlp.git"),
+            Some("github.com")
+        );
+        assert_eq!(
+            parse_host("https://user@gitlab.com:8443/group/project"),
+            Some("gitlab.com")
+        );
+        assert_eq!(
+            parse_host("ssh://git@example.com:2222/srv/repo.git"),
+            Some("example.com")
+        );
+        assert_eq!(
+            parse_host("git://git.kernel.org/pub/scm/git/git.git"),
+            Some("git.kernel.org")
+        );
+    }
+
+    #[test]
+    fn parse_host_from_scp_style_remotes() {
+        assert_eq!(
+            parse_host("git@github.com:yt-dlp/yt-dlp.git"),
+            Some("github.com")
+        );
+        assert_eq!(parse_host("example.com:repo.git"), Some("example.com"));
+    }
+
+    #[test]
+    fn parse_host_ignores_local_paths() {
+        assert_eq!(parse_host("/srv/git/repo.git"), None);
+        assert_eq!(parse_host("../repo"), None);
+        assert_eq!(parse_host("./dir:with/colon"), None);
+        assert_eq!(parse_host("file:///srv/git/repo.git"), None);
+    }
+
+    #[test]
+    fn parse_host_rejects_empty_hosts() {
+        assert_eq!(parse_host("https:///path"), None);
+        assert_eq!(parse_host("git@:repo.git"), None);
+    }
+}
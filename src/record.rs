@@ -0,0 +1,349 @@
+use std::{fmt, fs, path::Path};
+
+use jiff::{SignedDuration, Timestamp};
+use serde::{Deserialize, Serialize};
+
+use crate::{config::Settings, git};
+
+/// The version of the record format we write. Records written before there
+/// was a version (bare timestamps included) are upgraded as they're read.
+const VERSION: u32 = 2;
+
+/// After a failure, we leave a repository alone for this long before trying
+/// again. Each failure after that doubles the wait.
+const BACKOFF_BASE: SignedDuration = SignedDuration::from_mins(5);
+
+/// However many times a repository has failed, we'll give it another go at
+/// least this often.
+const BACKOFF_MAX: SignedDuration = SignedDuration::from_hours(6);
+
+/// What we know about a repository: where it is, when we last updated it
+/// and what that changed, and how our most recent attempt went.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(from = "Stored")]
+pub struct Record {
+    pub version: u32,
+
+    // Where the repository was when we last looked, and what it tracks.
+    // Handy when the key is a remote url or a commit hash, and nobody
+    // remembers where the thing actually lives.
+    pub path: Option<String>,
+    pub remote_url: Option<String>,
+    pub branch: Option<String>,
+
+    /// The time of the last successful update.
+    pub updated: Option<Timestamp>,
+    pub last_update: Option<Update>,
+    pub last_attempt: Option<Attempt>,
+
+    // Failures since the last successful update, which decide how long we
+    // leave the repository alone before trying again.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub consecutive_failures: u32,
+
+    // Whatever settings the last update was made with, other than the
+    // defaults, so that anyone reading the table later knows what "due"
+    // meant for this repository.
+    #[serde(default)]
+    pub overrides: Settings,
+}
+
+/// What the last successful update did.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Update {
+    pub head_before: Option<String>,
+    pub head_after: Option<String>,
+    pub duration: SignedDuration,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Attempt {
+    pub at: Timestamp,
+    pub result: AttemptResult,
+
+    // Set for attempts made in the background, until someone has been told
+    // how they went.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unreported: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "kebab-case")]
+pub enum AttemptResult {
+    Updated,
+    Skipped { reason: String },
+    Failed { error: String },
+    Running { pid: u32 },
+    TimedOut { after: SignedDuration },
+}
+
+impl Default for Record {
+    fn default() -> Self {
+        Record {
+            version: VERSION,
+            path: None,
+            remote_url: None,
+            branch: None,
+            updated: None,
+            last_update: None,
+            last_attempt: None,
+            consecutive_failures: 0,
+            overrides: Settings::default(),
+        }
+    }
+}
+
+impl Record {
+    /// Notes where the repository lives and what it tracks, as of now, along
+    /// with the settings we're updating it under.
+    pub fn locate(&mut self, repository: &str, settings: &Settings) {
+        let path = Path::new(repository);
+        self.path = fs::canonicalize(path)
+            .ok()
+            .map(|path| path.to_string_lossy().into());
+        self.remote_url =
+            git::remote_url(path, settings.remote.as_deref().unwrap_or("origin")).ok();
+        self.branch = git::output(path, &["symbolic-ref", "--quiet", "--short", "HEAD"]).ok();
+        self.overrides = settings.clone();
+    }
+
+    /// Records a successful update.
+    pub fn updated(&mut self, at: Timestamp, update: Update) {
+        self.updated = Some(at);
+        self.last_update = Some(update);
+        self.consecutive_failures = 0;
+        self.last_attempt = Some(Attempt {
+            at,
+            result: AttemptResult::Updated,
+            unreported: false,
+        });
+    }
+
+    /// Records an attempt that didn't update anything. The update time is
+    /// left alone, so the repository remains due.
+    pub fn attempted(&mut self, at: Timestamp, result: AttemptResult) {
+        if let AttemptResult::Failed { .. } | AttemptResult::TimedOut { .. } = result {
+            self.consecutive_failures += 1;
+        }
+        self.last_attempt = Some(Attempt {
+            at,
+            result,
+            unreported: false,
+        });
+    }
+
+    /// Flags the last attempt as one the user hasn't heard about yet.
+    pub fn mark_unreported(&mut self) {
+        if let Some(attempt) = &mut self.last_attempt {
+            attempt.unreported = true;
+        }
+    }
+
+    /// The process id of the background update working on this repository,
+    /// if there is one (or was one, if it died before finishing).
+    pub fn running(&self) -> Option<u32> {
+        match self.last_attempt {
+            Some(Attempt {
+                result: AttemptResult::Running { pid },
+                ..
+            }) => Some(pid),
+            _ => None,
+        }
+    }
+
+    /// When a repository that keeps failing is next worth a try, if that's
+    /// not right away. There's no sense hammering a remote that's gone.
+    pub fn retry_after(&self) -> Option<Timestamp> {
+        if self.consecutive_failures == 0 {
+            return None;
+        }
+
+        let attempt = self.last_attempt.as_ref()?;
+        let doublings = (self.consecutive_failures - 1).min(16);
+        let backoff = BACKOFF_BASE
+            .checked_mul(1 << doublings)
+            .map_or(BACKOFF_MAX, |backoff| backoff.min(BACKOFF_MAX));
+        attempt.at.checked_add(backoff).ok()
+    }
+
+    /// Describes the current run of failures, e.g. "failed 3 times in a row".
+    pub fn failure_streak(&self) -> String {
+        match self.consecutive_failures {
+            1 => String::from("failed once"),
+            n => format!("failed {n} times in a row"),
+        }
+    }
+
+    /// Returns the last attempt if nobody has heard how it went, and marks
+    /// it as heard.
+    pub fn take_report(&mut self) -> Option<&Attempt> {
+        let attempt = self.last_attempt.as_mut()?;
+        if !attempt.unreported || matches!(attempt.result, AttemptResult::Running { .. }) {
+            return None;
+        }
+
+        attempt.unreported = false;
+        Some(attempt)
+    }
+}
+
+impl fmt::Display for AttemptResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttemptResult::Updated => f.write_str("updated"),
+            AttemptResult::Skipped { reason } => write!(f, "skipped ({reason})"),
+            AttemptResult::Failed { error } => write!(f, "failed: {error}"),
+            AttemptResult::Running { pid } => write!(f, "running in the background (pid {pid})"),
+            AttemptResult::TimedOut { after } => write!(f, "timed out after {after:#}"),
+        }
+    }
+}
+
+/// The first version stored nothing but the time of the last update, so
+/// that's what we'll find in any table it wrote. Anything else is a record of
+/// some version or other; fields added since are simply missing.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Stored {
+    Legacy(Timestamp),
+    Structured(Box<Fields>),
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct Fields {
+    path: Option<String>,
+    remote_url: Option<String>,
+    branch: Option<String>,
+    updated: Option<Timestamp>,
+    last_update: Option<Update>,
+    last_attempt: Option<Attempt>,
+    consecutive_failures: u32,
+    overrides: Settings,
+}
+
+impl From<Stored> for Record {
+    fn from(stored: Stored) -> Self {
+        let fields = match stored {
+            Stored::Legacy(updated) => Fields {
+                updated: Some(updated),
+                ..Default::default()
+            },
+            Stored::Structured(fields) => *fields,
+        };
+
+        Record {
+            version: VERSION,
+            path: fields.path,
+            remote_url: fields.remote_url,
+            branch: fields.branch,
+            updated: fields.updated,
+            last_update: fields.last_update,
+            last_attempt: fields.last_attempt,
+            consecutive_failures: fields.consecutive_failures,
+            overrides: fields.overrides,
+        }
+    }
+}
+
+fn is_zero(n: &u32) -> bool {
+    *n == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: &str) -> Record {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn deserializes_a_bare_legacy_timestamp() {
+        let record = parse(r#""2024-05-01T12:00:00Z""#);
+        assert_eq!(record.version, VERSION);
+        assert_eq!(
+            record.updated,
+            Some("2024-05-01T12:00:00Z".parse().unwrap())
+        );
+        assert!(record.path.is_none());
+        assert!(record.last_attempt.is_none());
+        assert_eq!(record.consecutive_failures, 0);
+    }
+
+    #[test]
+    fn deserializes_a_partial_record() {
+        let record = parse(r#"{"updated": "2024-05-01T12:00:00Z", "path": "/src/tools"}"#);
+        assert_eq!(record.version, VERSION);
+        assert_eq!(
+            record.updated,
+            Some("2024-05-01T12:00:00Z".parse().unwrap())
+        );
+        assert_eq!(record.path.as_deref(), Some("/src/tools"));
+        assert!(record.remote_url.is_none());
+        assert!(record.last_update.is_none());
+        assert_eq!(record.consecutive_failures, 0);
+        assert!(record.overrides.max_age.is_none());
+    }
+
+    #[test]
+    fn deserializes_an_empty_record() {
+        let record = parse("{}");
+        assert!(record.updated.is_none());
+        assert_eq!(record.version, VERSION);
+    }
+
+    #[test]
+    fn round_trips_a_full_record() {
+        let mut record = Record::default();
+        let at: Timestamp = "2024-05-01T12:00:00Z".parse().unwrap();
+        record.updated(
+            at,
+            Update {
+                head_before: Some("abc".into()),
+                head_after: Some("def".into()),
+                duration: SignedDuration::from_secs(3),
+            },
+        );
+        record.attempted(
+            at,
+            AttemptResult::Failed {
+                error: "unable to reach the remote".into(),
+            },
+        );
+
+        let json = serde_json::to_string(&record).unwrap();
+        let read = parse(&json);
+        assert_eq!(read.updated, Some(at));
+        assert_eq!(read.consecutive_failures, 1);
+        assert_eq!(
+            read.last_update
+                .and_then(|update| update.head_after)
+                .as_deref(),
+            Some("def")
+        );
+        assert!(matches!(
+            read.last_attempt.map(|attempt| attempt.result),
+            Some(AttemptResult::Failed { .. })
+        ));
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_a_limit() {
+        let at: Timestamp = "2024-05-01T12:00:00Z".parse().unwrap();
+        let mut record = Record::default();
+        assert!(record.retry_after().is_none());
+
+        let failed = || AttemptResult::Failed {
+            error: String::from("nope"),
+        };
+        record.attempted(at, failed());
+        assert_eq!(record.retry_after(), at.checked_add(BACKOFF_BASE).ok());
+        record.attempted(at, failed());
+        assert_eq!(record.retry_after(), at.checked_add(BACKOFF_BASE * 2).ok());
+        for _ in 0..20 {
+            record.attempted(at, failed());
+        }
+        assert_eq!(record.retry_after(), at.checked_add(BACKOFF_MAX).ok());
+    }
+}
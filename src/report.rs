@@ -0,0 +1,280 @@
+use std::time::Duration;
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::{error::Error, hook::Ran, incoming::Incoming};
+
+/// How a run went, as told by its exit code.
+///
+/// When a run covers several repositories, the most serious outcome wins, so
+/// one failure isn't drowned out by a dozen successes. Variants are listed
+/// from least to most serious.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Exit {
+    /// Nothing needed doing.
+    Fresh,
+    /// The update was left to someone else: a background process, or another
+    /// copy of this program.
+    Deferred,
+    /// Updated, but nothing new came in.
+    Unchanged,
+    /// Updated, and HEAD moved.
+    Changed,
+    /// Not tried, because it's failed too often lately.
+    BackingOff,
+    /// Skipped, because the working tree wasn't safe to update.
+    Skipped,
+    /// Called off by a pre_update hook.
+    Vetoed,
+    /// Updated, but a hook failed afterwards.
+    HookFailed,
+    /// Not something we can update, e.g. not a directory.
+    Invalid,
+    /// The update failed, for the given reason.
+    Failed(Failure),
+    /// We were interrupted before we could finish.
+    Interrupted,
+}
+
+/// Why an update failed, as far as the exit code is concerned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Failure {
+    /// Git failed for some reason we didn't recognize, or something else did.
+    Other,
+    Table,
+    GitMissing,
+    Network,
+    Authentication,
+    Conflict,
+    Diverged,
+    NoUpstream,
+    TimedOut,
+}
+
+impl Exit {
+    /// The exit code for this outcome.
+    ///
+    /// Scripts written before there were any codes to speak of expect zero
+    /// for anything that didn't go wrong, and one for anything that did, so
+    /// that's what they get unless they ask for more.
+    pub fn code(self, detailed: bool) -> i32 {
+        if !detailed {
+            return match self {
+                Exit::Interrupted => 130,
+                Exit::HookFailed | Exit::Invalid | Exit::Failed(_) => 1,
+                _ => 0,
+            };
+        }
+
+        match self {
+            Exit::Fresh => 0,
+            Exit::Failed(Failure::Other) => 1,
+            Exit::Interrupted => 130,
+            Exit::Invalid => 3,
+            Exit::Failed(Failure::Table) => 4,
+            Exit::Failed(Failure::GitMissing) => 5,
+            Exit::Changed => 10,
+            Exit::Unchanged => 11,
+            Exit::Deferred => 12,
+            Exit::Skipped => 20,
+            Exit::BackingOff => 21,
+            Exit::HookFailed => 22,
+            Exit::Vetoed => 23,
+            Exit::Failed(Failure::Network) => 30,
+            Exit::Failed(Failure::Authentication) => 31,
+            Exit::Failed(Failure::Conflict) => 32,
+            Exit::Failed(Failure::Diverged) => 33,
+            Exit::Failed(Failure::NoUpstream) => 34,
+            Exit::Failed(Failure::TimedOut) => 35,
+        }
+    }
+
+    /// For commands that either work or don't.
+    pub fn from_success(success: bool) -> Self {
+        if success {
+            Exit::Fresh
+        } else {
+            Exit::Failed(Failure::Other)
+        }
+    }
+
+    /// Anything wrong with the repository we were given is the caller's
+    /// problem; anything else is ours (or git's), and sorted by kind.
+    pub fn from_error(error: &Error) -> Self {
+        let failure = match error {
+            Error::Interrupted => return Exit::Interrupted,
+            _ if error.is_invalid() => return Exit::Invalid,
+            Error::GaveUp { last, .. } => return Exit::from_error(last),
+            Error::LoadTable(_) | Error::StoreTable(_) => Failure::Table,
+            Error::GitMissing => Failure::GitMissing,
+            Error::Network { .. } => Failure::Network,
+            Error::Authentication(_) => Failure::Authentication,
+            Error::Conflict { .. } => Failure::Conflict,
+            Error::Diverged => Failure::Diverged,
+            Error::NoUpstream => Failure::NoUpstream,
+            Error::TimedOut(_) => Failure::TimedOut,
+            Error::NotADirectory | Error::NotARepository | Error::Git(_) | Error::Io(_) => {
+                Failure::Other
+            }
+        };
+        Exit::Failed(failure)
+    }
+
+    /// Whether the run went well enough for a wrapped command to go ahead.
+    pub fn success(self) -> bool {
+        self < Exit::Invalid
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// A line per repository, when there's more than one (or with --verbose).
+    #[default]
+    Text,
+    /// A single JSON document on stdout.
+    Json,
+}
+
+/// The machine-readable account of a run. A run that couldn't get as far as
+/// any one repository (a bad config file, a table we can't read) has none to
+/// report on, just the error.
+#[derive(Serialize)]
+pub struct Report<'a> {
+    pub exit_code: i32,
+    pub repositories: Vec<Entry<'a>>,
+    pub error: Option<String>,
+    pub error_kind: Option<&'static str>,
+}
+
+/// What happened to one repository.
+#[derive(Serialize)]
+pub struct Entry<'a> {
+    pub repository: &'a str,
+    pub decision: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub old_head: Option<&'a str>,
+    pub new_head: Option<&'a str>,
+    #[serde(serialize_with = "seconds")]
+    pub duration: Option<Duration>,
+    pub attempts: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changes: Option<&'a Incoming>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    pub hooks: &'a [Ran],
+    pub error: Option<String>,
+    pub error_kind: Option<&'static str>,
+}
+
+impl<'a> Entry<'a> {
+    pub fn new(repository: &'a str, decision: &'static str) -> Self {
+        Entry {
+            repository,
+            decision,
+            reason: None,
+            old_head: None,
+            new_head: None,
+            duration: None,
+            attempts: None,
+            changes: None,
+            hooks: &[],
+            error: None,
+            error_kind: None,
+        }
+    }
+}
+
+fn seconds<S: serde::Serializer>(duration: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+    match duration {
+        Some(duration) => s.serialize_f64(duration.as_secs_f64()),
+        None => s.serialize_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plain_codes_are_zero_unless_something_went_wrong() {
+        for exit in [
+            Exit::Fresh,
+            Exit::Deferred,
+            Exit::Unchanged,
+            Exit::Changed,
+            Exit::BackingOff,
+            Exit::Skipped,
+            Exit::Vetoed,
+        ] {
+            assert_eq!(exit.code(false), 0, "{exit:?}");
+        }
+        for exit in [
+            Exit::HookFailed,
+            Exit::Invalid,
+            Exit::Failed(Failure::Other),
+            Exit::Failed(Failure::Network),
+        ] {
+            assert_eq!(exit.code(false), 1, "{exit:?}");
+        }
+        assert_eq!(Exit::Interrupted.code(false), 130);
+    }
+
+    #[test]
+    fn detailed_codes_tell_outcomes_apart() {
+        assert_eq!(Exit::Fresh.code(true), 0);
+        assert_eq!(Exit::Changed.code(true), 10);
+        assert_eq!(Exit::Unchanged.code(true), 11);
+        assert_eq!(Exit::Skipped.code(true), 20);
+        assert_eq!(Exit::Invalid.code(true), 3);
+        assert_eq!(Exit::Interrupted.code(true), 130);
+    }
+
+    #[test]
+    fn failure_classes_get_codes_of_their_own() {
+        let errors = [
+            Error::Git(String::from("git pull failed")),
+            Error::LoadTable(std::io::Error::other("nope")),
+            Error::GitMissing,
+            Error::Network {
+                reason: String::new(),
+                transient: true,
+            },
+            Error::Authentication(String::new()),
+            Error::Conflict { aborted: true },
+            Error::Diverged,
+            Error::NoUpstream,
+            Error::TimedOut(Duration::from_secs(1)),
+        ];
+        let mut codes: Vec<_> = errors
+            .iter()
+            .map(|e| Exit::from_error(e).code(true))
+            .collect();
+        assert_eq!(codes[0], 1);
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+    }
+
+    #[test]
+    fn giving_up_keeps_the_class_of_the_last_failure() {
+        let error = Error::GaveUp {
+            attempts: 3,
+            last: Box::new(Error::Network {
+                reason: String::new(),
+                transient: true,
+            }),
+        };
+        assert_eq!(Exit::from_error(&error), Exit::Failed(Failure::Network));
+        assert_eq!(Exit::from_error(&Error::Interrupted), Exit::Interrupted);
+        assert_eq!(Exit::from_error(&Error::NotADirectory), Exit::Invalid);
+    }
+
+    #[test]
+    fn failures_outrank_everything_but_interruptions() {
+        assert!(Exit::Failed(Failure::Other) > Exit::Skipped);
+        assert!(Exit::Interrupted > Exit::Failed(Failure::TimedOut));
+        assert!(Exit::HookFailed.success());
+        assert!(!Exit::Failed(Failure::Other).success());
+    }
+}
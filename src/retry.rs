@@ -0,0 +1,31 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+/// The wait before the first retry. Each retry after that waits twice as long
+/// as the one before.
+const BASE_DELAY: Duration = Duration::from_secs(1);
+
+/// However many times we've failed, we won't wait longer than this.
+const MAX_DELAY: Duration = Duration::from_secs(30);
+
+/// How long to wait before trying again, having failed `failures` times.
+///
+/// The delay is jittered by up to half either way, so that a dozen updates
+/// that failed together (say, because the wifi dropped) don't all come back
+/// at the same moment and fail together again.
+pub fn delay(failures: u32) -> Duration {
+    let exponent = failures.saturating_sub(1).min(16);
+    let delay = BASE_DELAY.saturating_mul(1 << exponent).min(MAX_DELAY);
+    delay.mul_f64(0.5 + random())
+}
+
+/// A number in [0, 1), good enough for jitter and nothing else.
+fn random() -> f64 {
+    // Every RandomState is seeded differently, which is all the randomness
+    // we need and saves us a dependency.
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
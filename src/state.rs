@@ -0,0 +1,104 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io,
+    time::Duration,
+};
+
+use abseil::Provider;
+
+use crate::{error::Error, lock::Lock, record::Record};
+
+/// Repository keys and what we know about each repository.
+pub type Table = HashMap<String, Record>;
+
+/// Nobody holds the table lock for longer than it takes to read and write a
+/// small file, so if we've waited this long, something is wrong.
+const TABLE_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The state table, shared between every running copy of this program.
+///
+/// Reads and writes happen under a lock, and writes are read-modify-write:
+/// the table is reloaded just before our changes are applied, so that
+/// changes made by anybody else in the meantime survive.
+pub struct State {
+    provider: Provider,
+}
+
+impl State {
+    pub fn new() -> Self {
+        let provider = Provider::builder("ensure-update")
+            .with_organization("hack-commons")
+            .build();
+        State { provider }
+    }
+
+    /// Reads the table as it stands right now.
+    pub fn load(&self) -> Result<Table, Error> {
+        self.read().map_err(Error::LoadTable)
+    }
+
+    /// Applies changes to the latest version of the table and stores it.
+    pub fn modify(&self, f: impl FnOnce(&mut Table)) -> Result<(), Error> {
+        self.write(f).map_err(Error::StoreTable)
+    }
+
+    fn read(&self) -> io::Result<Table> {
+        let _lock = Lock::acquire(&Lock::table()?, Some(TABLE_LOCK_TIMEOUT))?;
+        Ok(self.provider.load()?.into_inner())
+    }
+
+    fn write(&self, f: impl FnOnce(&mut Table)) -> io::Result<()> {
+        let _lock = Lock::acquire(&Lock::table()?, Some(TABLE_LOCK_TIMEOUT))?;
+        let mut table: Table = self.provider.load()?.into_inner();
+        f(&mut table);
+        self.provider.store(table)?;
+        Ok(())
+    }
+}
+
+/// Changes made to our own copy of the table, waiting to be applied to the
+/// latest version of it.
+#[derive(Debug, Default)]
+pub struct Changes {
+    touched: HashSet<String>,
+    removed: HashSet<String>,
+}
+
+impl Changes {
+    /// Notes that the record under `key` has changed.
+    pub fn touch(&mut self, key: &str) {
+        self.removed.remove(key);
+        self.touched.insert(key.into());
+    }
+
+    /// Notes that the record under `key` has been removed.
+    pub fn remove(&mut self, key: &str) {
+        self.touched.remove(key);
+        self.removed.insert(key.into());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.touched.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl State {
+    /// Stores whatever we changed in our copy of the table, leaving anything
+    /// else as it is in the latest version.
+    pub fn commit(&self, table: &Table, changes: &Changes) -> Result<(), Error> {
+        if changes.is_empty() {
+            return Ok(());
+        }
+
+        self.modify(|latest| {
+            for key in &changes.removed {
+                latest.remove(key);
+            }
+            for key in &changes.touched {
+                if let Some(record) = table.get(key) {
+                    latest.insert(key.clone(), record.clone());
+                }
+            }
+        })
+    }
+}
@@ -0,0 +1,142 @@
+use std::io;
+
+use jiff::{Timestamp, tz::TimeZone};
+use serde::Serialize;
+
+use crate::{
+    age::{self, MaxAge},
+    config::{Config, Settings},
+    record::{Attempt, Update},
+    state::Table,
+};
+
+/// A line in the status report.
+#[derive(Serialize)]
+struct Entry<'a> {
+    repository: &'a str,
+    path: Option<&'a str>,
+    remote_url: Option<&'a str>,
+    branch: Option<&'a str>,
+    updated: Option<Timestamp>,
+    age_seconds: Option<i64>,
+    due: bool,
+    next_due: Option<Timestamp>,
+    last_update: Option<&'a Update>,
+    last_attempt: Option<&'a Attempt>,
+    consecutive_failures: u32,
+    #[serde(skip)]
+    streak: String,
+    retry_after: Option<Timestamp>,
+}
+
+/// Prints what we know about every repository in the table.
+///
+/// Max ages come from the command line if given, then from the config file.
+/// Repositories keyed by something other than their path can't be matched
+/// up with the config, so they get the default.
+pub fn print(
+    table: &Table,
+    config: &Config,
+    max_age: Option<MaxAge>,
+    json: bool,
+) -> io::Result<()> {
+    let now = Timestamp::now();
+
+    let mut keys: Vec<_> = table.keys().collect();
+    keys.sort();
+
+    let entries: Vec<_> = keys
+        .into_iter()
+        .map(|key| {
+            let record = &table[key];
+            let max_age = Settings {
+                max_age,
+                ..Default::default()
+            }
+            .or(config.settings(key))
+            .max_age();
+            let next_due = record
+                .updated
+                .and_then(|updated| updated.checked_add(max_age.duration()).ok());
+
+            Entry {
+                repository: key,
+                path: record.path.as_deref(),
+                remote_url: record.remote_url.as_deref(),
+                branch: record.branch.as_deref(),
+                updated: record.updated,
+                age_seconds: record
+                    .updated
+                    .map(|updated| now.duration_since(updated).as_secs()),
+                due: next_due.is_none_or(|next_due| next_due <= now),
+                next_due,
+                last_update: record.last_update.as_ref(),
+                last_attempt: record.last_attempt.as_ref(),
+                consecutive_failures: record.consecutive_failures,
+                streak: record.failure_streak(),
+                retry_after: record.retry_after().filter(|until| *until > now),
+            }
+        })
+        .collect();
+
+    if json {
+        let json = serde_json::to_string_pretty(&entries).map_err(io::Error::other)?;
+        println!("{json}");
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        println!("no repositories tracked yet");
+        return Ok(());
+    }
+
+    let rows: Vec<[String; 4]> = entries.iter().map(|entry| row(entry, now)).collect();
+    let widths: Vec<_> = (0..4)
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+
+    for [repository, updated, due, last] in rows {
+        println!(
+            "{repository:<w0$}  {updated:<w1$}  {due:<w2$}  {last}",
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+        );
+    }
+
+    Ok(())
+}
+
+fn row(entry: &Entry, now: Timestamp) -> [String; 4] {
+    let updated = match entry.updated {
+        Some(updated) => {
+            let local = updated.to_zoned(TimeZone::system()).strftime("%F %R");
+            let ago = age::friendly(now.duration_since(updated));
+            format!("{local} ({ago} ago)")
+        }
+        None => String::from("never updated"),
+    };
+
+    let due = match entry.next_due {
+        Some(next_due) if next_due > now => {
+            format!("due in {}", age::friendly(next_due.duration_since(now)))
+        }
+        Some(next_due) => format!("overdue by {}", age::friendly(now.duration_since(next_due))),
+        None => String::from("due"),
+    };
+
+    let mut last = match entry.last_attempt {
+        Some(attempt) => {
+            let ago = age::friendly(now.duration_since(attempt.at));
+            format!("last attempt {ago} ago: {}", attempt.result)
+        }
+        None => String::new(),
+    };
+
+    if let Some(until) = entry.retry_after {
+        let wait = age::friendly(until.duration_since(now));
+        last.push_str(&format!(" ({}; next try in {wait})", entry.streak));
+    }
+
+    [entry.repository.into(), updated, due, last]
+}
@@ -0,0 +1,244 @@
+use std::{io, path::Path, process::Command};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::git;
+
+/// How a repository is brought up to date, and what happens when the local
+/// branch has diverged from its upstream.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// Plain `git pull`; a diverged branch is up to pull.rebase and pull.ff.
+    #[default]
+    Pull,
+    /// Fast-forward only; a diverged branch fails the update and is left alone.
+    FfOnly,
+    /// Rebase local commits onto upstream; on conflict, the rebase is aborted.
+    Rebase,
+    /// As rebase, but stashes uncommitted changes first and restores them after.
+    RebaseAutostash,
+    /// Fetch without touching the working tree; divergence doesn't matter.
+    FetchOnly,
+    /// Hard reset to upstream, discarding local commits and changes. Requires
+    /// --allow-reset.
+    ResetToUpstream,
+}
+
+impl Strategy {
+    /// Builds the commands that carry out this strategy, to be run in order.
+    ///
+    /// With `autostash`, uncommitted changes are stashed before the update and
+    /// restored after, for those strategies that would otherwise trip over
+    /// them.
+    pub fn commands(
+        self,
+        remote: Option<&str>,
+        branch: Option<&str>,
+        autostash: bool,
+        allow_reset: bool,
+    ) -> io::Result<Vec<Command>> {
+        // Git won't take a branch without a remote, so if we've been given
+        // only a branch, we'll assume the remote is origin.
+        let remote = remote.or(branch.map(|_| "origin"));
+        let target: Vec<&str> = remote.into_iter().chain(branch).collect();
+        let pull = |flags: &[&str]| {
+            let mut command = git_command(&["pull"], flags);
+            if autostash {
+                command.arg("--autostash");
+            }
+            command.args(&target);
+            command
+        };
+
+        let commands = match self {
+            Strategy::Pull => vec![pull(&[])],
+            Strategy::FfOnly => vec![pull(&["--ff-only"])],
+            Strategy::Rebase => vec![pull(&["--rebase"])],
+            Strategy::RebaseAutostash => {
+                vec![git_command(&["pull", "--rebase", "--autostash"], &target)]
+            }
+            Strategy::FetchOnly => vec![git_command(&["fetch"], &target)],
+            Strategy::ResetToUpstream => {
+                if !allow_reset {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        "reset-to-upstream discards local work and requires --allow-reset",
+                    ));
+                }
+
+                let upstream = match (remote, branch) {
+                    (Some(remote), Some(branch)) => format!("{remote}/{branch}"),
+                    _ => String::from("@{upstream}"),
+                };
+
+                vec![
+                    git_command(&["fetch"], &target),
+                    git_command(&["reset", "--hard"], &[&upstream]),
+                ]
+            }
+        };
+
+        Ok(commands)
+    }
+
+    /// Cleans up after a failed update, so that the repository is left the way
+    /// we found it. Returns true if there was a rebase to abort.
+    pub fn recover(self, repository: &str) -> bool {
+        // A failed merge leaves conflicts for the user to sort out, same as it
+        // always has. A failed rebase, on the other hand, leaves the repository
+        // mid-rebase, which is no state to be in when you weren't expecting
+        // it, so we back out of it.
+        if !matches!(self, Strategy::Rebase | Strategy::RebaseAutostash) {
+            return false;
+        }
+
+        let path = Path::new(repository);
+        let in_progress = ["rebase-merge", "rebase-apply"].iter().any(|name| {
+            git::output(path, &["rev-parse", "--git-path", name])
+                .is_ok_and(|git_path| path.join(git_path).exists())
+        });
+
+        in_progress && git::output(path, &["rebase", "--abort"]).is_ok()
+    }
+
+    /// Explains a failed update in terms of this strategy.
+    pub fn failure(self) -> &'static str {
+        match self {
+            Strategy::Pull => "repository failed to update",
+            Strategy::FfOnly => {
+                "repository failed to update (cannot fast-forward; has it diverged?)"
+            }
+            Strategy::Rebase | Strategy::RebaseAutostash => {
+                "repository failed to update (any conflicted rebase was aborted)"
+            }
+            Strategy::FetchOnly => "repository failed to fetch",
+            Strategy::ResetToUpstream => "repository failed to reset to upstream",
+        }
+    }
+}
+
+fn git_command(args: &[&str], target: &[&str]) -> Command {
+    let mut command = git::command();
+    command.args(args).args(target);
+    command
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(
+        strategy: Strategy,
+        remote: Option<&str>,
+        branch: Option<&str>,
+        autostash: bool,
+    ) -> Vec<Vec<String>> {
+        strategy
+            .commands(remote, branch, autostash, true)
+            .unwrap()
+            .iter()
+            .map(|command| {
+                command
+                    .get_args()
+                    .map(|arg| arg.to_string_lossy().into_owned())
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn builds_each_strategy() {
+        let cases: &[(Strategy, &[&[&str]])] = &[
+            (Strategy::Pull, &[&["pull"]]),
+            (Strategy::FfOnly, &[&["pull", "--ff-only"]]),
+            (Strategy::Rebase, &[&["pull", "--rebase"]]),
+            (
+                Strategy::RebaseAutostash,
+                &[&["pull", "--rebase", "--autostash"]],
+            ),
+            (Strategy::FetchOnly, &[&["fetch"]]),
+            (
+                Strategy::ResetToUpstream,
+                &[&["fetch"], &["reset", "--hard", "@{upstream}"]],
+            ),
+        ];
+        for (strategy, expected) in cases {
+            assert_eq!(
+                args(*strategy, None, None, false),
+                *expected,
+                "{strategy:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn passes_the_remote_and_branch() {
+        assert_eq!(
+            args(Strategy::FfOnly, Some("upstream"), Some("main"), false),
+            [["pull", "--ff-only", "upstream", "main"]]
+        );
+        assert_eq!(
+            args(
+                Strategy::ResetToUpstream,
+                Some("upstream"),
+                Some("main"),
+                false
+            ),
+            [
+                &["fetch", "upstream", "main"][..],
+                &["reset", "--hard", "upstream/main"]
+            ]
+        );
+    }
+
+    #[test]
+    fn a_branch_alone_comes_from_origin() {
+        assert_eq!(
+            args(Strategy::Pull, None, Some("main"), false),
+            [["pull", "origin", "main"]]
+        );
+        assert_eq!(
+            args(Strategy::ResetToUpstream, None, Some("main"), false)[1],
+            ["reset", "--hard", "origin/main"]
+        );
+    }
+
+    #[test]
+    fn a_remote_alone_resets_to_the_configured_upstream() {
+        assert_eq!(
+            args(Strategy::ResetToUpstream, Some("upstream"), None, false),
+            [
+                &["fetch", "upstream"][..],
+                &["reset", "--hard", "@{upstream}"]
+            ]
+        );
+    }
+
+    #[test]
+    fn autostash_goes_before_the_target() {
+        assert_eq!(
+            args(Strategy::Rebase, Some("upstream"), Some("main"), true),
+            [["pull", "--rebase", "--autostash", "upstream", "main"]]
+        );
+        // Only pulls have changes to stash around.
+        assert_eq!(args(Strategy::FetchOnly, None, None, true), [["fetch"]]);
+        assert_eq!(
+            args(Strategy::RebaseAutostash, None, None, true),
+            [["pull", "--rebase", "--autostash"]]
+        );
+    }
+
+    #[test]
+    fn reset_requires_permission() {
+        let error = Strategy::ResetToUpstream
+            .commands(None, None, false, false)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+
+        for strategy in [Strategy::Pull, Strategy::FetchOnly] {
+            assert!(strategy.commands(None, None, false, false).is_ok());
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::{path::Path, process::Stdio, time::Duration};
+
+use crate::{error::Error, git};
+
+/// Where a repository's updates come from: a branch on a remote, and the
+/// ref that tracks it locally.
+#[derive(Debug)]
+pub struct Upstream {
+    pub remote: String,
+    pub remote_ref: String,
+    pub tracking_ref: String,
+}
+
+impl Upstream {
+    /// Works out the upstream for the branch that's checked out, or for the
+    /// given remote and branch, if there are any. As with the update itself,
+    /// a branch without a remote is taken to be on origin.
+    pub fn resolve(
+        repository: &Path,
+        remote: Option<&str>,
+        branch: Option<&str>,
+    ) -> Result<Upstream, Error> {
+        if let Some(branch) = branch {
+            let remote = remote.unwrap_or("origin");
+            return Ok(Upstream {
+                remote: remote.into(),
+                remote_ref: format!("refs/heads/{branch}"),
+                tracking_ref: format!("refs/remotes/{remote}/{branch}"),
+            });
+        }
+
+        // A detached HEAD has no upstream, and neither does a branch nobody
+        // set one up for.
+        let head = git::output(repository, &["symbolic-ref", "--quiet", "HEAD"])
+            .map_err(|_| Error::NoUpstream)?;
+        let format = "--format=%(upstream:remotename)%00%(upstream:remoteref)%00%(upstream)";
+        let fields = git::output(repository, &["for-each-ref", format, &head])?;
+
+        let mut fields = fields.split('\0');
+        let (Some(name), Some(remote_ref), Some(tracking_ref)) =
+            (fields.next(), fields.next(), fields.next())
+        else {
+            return Err(Error::NoUpstream);
+        };
+        if name.is_empty() || remote_ref.is_empty() || tracking_ref.is_empty() {
+            return Err(Error::NoUpstream);
+        }
+
+        // Asked for a different remote, we'll look for the same branch there.
+        let upstream = match remote {
+            Some(remote) if remote != name => {
+                let branch = remote_ref.trim_start_matches("refs/heads/");
+                Upstream {
+                    remote: remote.into(),
+                    remote_ref: remote_ref.into(),
+                    tracking_ref: format!("refs/remotes/{remote}/{branch}"),
+                }
+            }
+            _ => Upstream {
+                remote: name.into(),
+                remote_ref: remote_ref.into(),
+                tracking_ref: tracking_ref.into(),
+            },
+        };
+
+        Ok(upstream)
+    }
+
+    /// The commit upstream was at when we last fetched it, if we ever have.
+    pub fn local(&self, repository: &Path) -> Option<String> {
+        git::output(
+            repository,
+            &["rev-parse", "--verify", "--quiet", &self.tracking_ref],
+        )
+        .ok()
+    }
+
+    /// The commit upstream is at right now, according to the remote. This is
+    /// a single round trip, and a small one, so it's a good deal cheaper than
+    /// a fetch.
+    pub fn remote(
+        &self,
+        repository: &Path,
+        non_interactive: bool,
+        timeout: Option<Duration>,
+    ) -> Result<Option<String>, Error> {
+        let mut command = git::command();
+        command
+            .arg("-C")
+            .arg(repository)
+            .args(["ls-remote", &self.remote, &self.remote_ref])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped());
+        if non_interactive {
+            git::non_interactive(&mut command, repository);
+        }
+
+        let finished = git::run(&mut command, false, timeout, non_interactive)?;
+        if !finished.status.success() {
+            let stderr = String::from_utf8_lossy(&finished.stderr);
+            return Err(Error::classify(&stderr)
+                .unwrap_or_else(|| Error::Git(String::from("git ls-remote failed"))));
+        }
+
+        // Each line is a hash and a ref name. We asked for exactly one ref,
+        // so there's either one line or none.
+        let stdout = String::from_utf8_lossy(&finished.stdout);
+        let hash = stdout
+            .lines()
+            .find_map(|line| line.split_whitespace().next())
+            .map(String::from);
+        Ok(hash)
+    }
+
+    /// Whether the remote has moved on since we last fetched.
+    pub fn moved(
+        &self,
+        repository: &Path,
+        non_interactive: bool,
+        timeout: Option<Duration>,
+    ) -> Result<bool, Error> {
+        let remote = self.remote(repository, non_interactive, timeout)?;
+        Ok(remote.is_none() || remote != self.local(repository))
+    }
+}
@@ -0,0 +1,99 @@
+use std::{fmt, path::Path};
+
+use crate::{error::Error, git};
+
+/// Something about the state of a working tree that makes it unsafe to
+/// update.
+#[derive(Debug)]
+pub enum Blocker {
+    /// Tracked files have been modified or staged.
+    Uncommitted,
+    /// Untracked files sit where incoming changes would put tracked ones.
+    Untracked(usize),
+    /// Another git process is using the index (or crashed while using it).
+    IndexLock,
+    /// A rebase, merge, etc. has been started and not finished.
+    InProgress(&'static str),
+}
+
+impl Blocker {
+    /// Autostash takes care of uncommitted changes, but not of anything else.
+    pub fn autostashable(&self) -> bool {
+        matches!(self, Blocker::Uncommitted)
+    }
+}
+
+impl fmt::Display for Blocker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Blocker::Uncommitted => f.write_str("uncommitted changes"),
+            Blocker::Untracked(1) => f.write_str("an untracked file would be overwritten"),
+            Blocker::Untracked(n) => write!(f, "{n} untracked files would be overwritten"),
+            Blocker::IndexLock => f.write_str("index.lock exists"),
+            Blocker::InProgress(operation) => write!(f, "{operation} in progress"),
+        }
+    }
+}
+
+/// Looks for anything that should stop us from updating the repository.
+///
+/// With `check_changes` false, only the things that would trip up any git
+/// operation at all (a locked index, an unfinished rebase) are considered.
+pub fn check(repository: &str, check_changes: bool) -> Result<Option<Blocker>, Error> {
+    let path = Path::new(repository);
+
+    if git_path_exists(path, "index.lock")? {
+        return Ok(Some(Blocker::IndexLock));
+    }
+
+    const OPERATIONS: &[(&str, &str)] = &[
+        ("rebase-merge", "rebase"),
+        ("rebase-apply", "rebase"),
+        ("MERGE_HEAD", "merge"),
+        ("CHERRY_PICK_HEAD", "cherry-pick"),
+        ("REVERT_HEAD", "revert"),
+        ("BISECT_LOG", "bisect"),
+    ];
+
+    for &(name, operation) in OPERATIONS {
+        if git_path_exists(path, name)? {
+            return Ok(Some(Blocker::InProgress(operation)));
+        }
+    }
+
+    if !check_changes {
+        return Ok(None);
+    }
+
+    let status = git::output(path, &["status", "--porcelain", "--untracked-files=no"])?;
+    if !status.is_empty() {
+        return Ok(Some(Blocker::Uncommitted));
+    }
+
+    // We can't know for sure what's coming without fetching, but whatever we
+    // fetched last time is a pretty good guess. No upstream means nothing to
+    // compare against, which is fine: git will complain about that on its own.
+    let untracked = git::output(path, &["ls-files", "--others", "--exclude-standard"])?;
+    if untracked.is_empty() {
+        return Ok(None);
+    }
+
+    let Ok(incoming) = git::output(path, &["diff", "--name-only", "HEAD", "@{upstream}"]) else {
+        return Ok(None);
+    };
+
+    let incoming: Vec<_> = incoming.lines().collect();
+    let overwritten = untracked
+        .lines()
+        .filter(|file| incoming.contains(file))
+        .count();
+
+    Ok((overwritten > 0).then_some(Blocker::Untracked(overwritten)))
+}
+
+fn git_path_exists(path: &Path, name: &str) -> Result<bool, Error> {
+    // --git-path takes care of worktrees and unusual layouts for us, and
+    // hands back a path relative to the repository.
+    let git_path = git::output(path, &["rev-parse", "--git-path", name])?;
+    Ok(path.join(git_path).exists())
+}